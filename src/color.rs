@@ -0,0 +1,99 @@
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+
+const DEFAULT_DIR_COLOR: &str = "01;34";
+const DEFAULT_SYMLINK_COLOR: &str = "01;36";
+const MATCH_HIGHLIGHT_COLOR: &str = "01;31";
+const RESET: &[u8] = b"\x1b[0m";
+
+#[derive(PartialEq, Clone, Copy)]
+pub enum ColorWhen {
+    Auto,
+    Always,
+    Never,
+}
+
+// Colors, the colors for each kind of entry, taken from `LS_COLORS` (falling back to `ls`'s defaults for dirs and
+// symlinks). Only the 'di', 'ln' and 'fi' keys and suffix patterns (e.g. '*.rs') are used, since anything else would need
+// an extra syscall per entry
+pub struct Colors {
+    dir: Option<String>,
+    symlink: Option<String>,
+    file: Option<String>,
+    suffixes: Vec<(Vec<u8>, String)>,
+}
+
+impl Colors {
+    pub fn from_env() -> Colors {
+        let mut ret = Colors { dir: Some(DEFAULT_DIR_COLOR.to_string()), symlink: Some(DEFAULT_SYMLINK_COLOR.to_string()), file: None, suffixes: Vec::new() };
+        let Ok(ls_colors) = std::env::var("LS_COLORS") else { return ret };
+        for entry in ls_colors.split(':') {
+            let Some((key, val)) = entry.split_once('=') else { continue };
+            let color = if val.len() > 0 && val != "0" && val != "00" { Some(val.to_string()) } else { None };
+            match key {
+                "di" => { ret.dir = color; }
+                "ln" => { ret.symlink = color; }
+                "fi" => { ret.file = color; }
+                _ => {
+                    if let (Some(suffix), Some(color)) = (key.strip_prefix('*'), color) {
+                        ret.suffixes.push((suffix.as_bytes().to_vec(), color));
+                    }
+                }
+            }
+        }
+        return ret;
+    }
+
+    pub fn kind_color(&self, base_name: &[u8], is_file: bool, is_symlink: bool) -> Option<&str> {
+        if is_symlink {
+            return self.symlink.as_deref();
+        } else if !is_file {
+            return self.dir.as_deref();
+        }
+        for (suffix, color) in &self.suffixes {
+            if base_name.ends_with(suffix) {
+                return Some(color);
+            }
+        }
+        return self.file.as_deref();
+    }
+
+    // colorize, colors the basename of `path` by its kind and highlights the matched span of the basename (if any)
+    pub fn colorize(&self, path: &Path, match_span: Option<(usize, usize)>, is_file: bool, is_symlink: bool) -> Vec<u8> {
+        let path_bytes = path.as_os_str().as_bytes();
+        let trimmed = path_bytes.strip_suffix(b"/").unwrap_or(path_bytes);
+        let base_start = trimmed.iter().rposition(|b| *b == b'/').map_or(0, |i| i + 1);
+        let base_name = &trimmed[base_start..];
+        let kind_color = self.kind_color(base_name, is_file, is_symlink);
+
+        let mut ret = Vec::with_capacity(path_bytes.len() + 32);
+        ret.extend_from_slice(&path_bytes[..base_start]);
+        let (hl_start, hl_end) = match_span.unwrap_or((base_name.len(), base_name.len()));
+        push_colored(&mut ret, &base_name[..hl_start], kind_color);
+        push_colored(&mut ret, &base_name[hl_start..hl_end], Some(MATCH_HIGHLIGHT_COLOR));
+        push_colored(&mut ret, &base_name[hl_end..], kind_color);
+        ret.extend_from_slice(&path_bytes[trimmed.len()..]);
+        return ret;
+    }
+
+    pub fn colorize_label(&self, label: &str, is_file: bool, is_symlink: bool) -> Vec<u8> {
+        let mut ret = Vec::with_capacity(label.len() + 16);
+        push_colored(&mut ret, label.as_bytes(), self.kind_color(b"", is_file, is_symlink));
+        return ret;
+    }
+}
+
+fn push_colored(out: &mut Vec<u8>, s: &[u8], color: Option<&str>) {
+    if s.len() == 0 {
+        return;
+    }
+    let Some(color) = color else {
+        out.extend_from_slice(s);
+        return;
+    };
+    out.extend_from_slice(b"\x1b[");
+    out.extend_from_slice(color.as_bytes());
+    out.push(b'm');
+    out.extend_from_slice(s);
+    out.extend_from_slice(RESET);
+}
@@ -0,0 +1,78 @@
+use regex::bytes::Regex;
+use std::io::Read;
+use std::path::Path;
+
+pub const DEFAULT_MAX_FILESIZE: u64 = 32 * 1024 * 1024;
+const READ_CHUNK_SIZE: usize = 64 * 1024;
+
+// ContentMatcher, matches files by their contents with `--contains`. Contents are searched a line at a time (the same as
+// `grep`), so a pattern can't match across lines
+pub struct ContentMatcher {
+    pub rx: Regex,
+    pub max_filesize: u64,
+    pub search_binary: bool,
+}
+
+impl ContentMatcher {
+    // new, with `is_literal` the pattern is matched as is, rather than as a regex
+    pub fn new(pattern: &str, is_literal: bool) -> Result<ContentMatcher, String> {
+        // Multi-line mode, so '^' and '$' match at the start and end of each line
+        let rx_str = format!("(?m){}", if is_literal { regex::escape(pattern) } else { pattern.to_string() });
+        let Ok(rx) = Regex::new(&rx_str) else {
+            return Err(format!("invalid `--contains` pattern '{}'", pattern));
+        };
+        return Ok(ContentMatcher { rx, max_filesize: DEFAULT_MAX_FILESIZE, search_binary: false });
+    }
+
+    // num_matches, the number of matches in the file at `path`, stopping at the first one unless `count_all`. Anything
+    // that isn't a regular file (after following symlinks), is larger than `max_filesize`, unreadable or binary (has a
+    // NUL byte in its first chunk) has none
+    pub fn num_matches(&self, path: &Path, count_all: bool) -> usize {
+        let Ok(mut file) = std::fs::File::open(path) else { return 0 };
+        let Ok(md) = file.metadata() else { return 0 };
+        if !md.is_file() || md.len() > self.max_filesize {
+            return 0;
+        }
+
+        let mut ret = 0;
+        let mut buf: Vec<u8> = Vec::with_capacity(READ_CHUNK_SIZE);
+        let mut chunk = vec![0u8; READ_CHUNK_SIZE];
+        let mut is_first_chunk = true;
+        loop {
+            let num_read = match file.read(&mut chunk) {
+                Ok(n) => n,
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+                Err(_) => return ret,
+            };
+            if is_first_chunk && !self.search_binary && chunk[..num_read].contains(&0) {
+                return 0;
+            }
+            is_first_chunk = false;
+            buf.extend_from_slice(&chunk[..num_read]);
+
+            // Only whole lines are searched, a partial last line is kept until the rest of it is read
+            let is_eof = num_read == 0;
+            let search_len = if is_eof { buf.len() } else { buf.iter().rposition(|b| *b == b'\n').map_or(0, |idx| idx + 1) };
+            if count_all {
+                ret += self.rx.find_iter(&buf[..search_len]).count();
+            } else if self.rx.is_match(&buf[..search_len]) {
+                return 1;
+            }
+            if is_eof {
+                return ret;
+            }
+            buf.drain(..search_len);
+        }
+    }
+}
+
+// parse_size, a number of bytes with an optional K, M or G suffix (powers of 1024), e.g. '10M'
+pub fn parse_size(s: &str) -> Option<u64> {
+    let (num, multiplier) = match s.char_indices().last() {
+        Some((idx, 'K' | 'k')) => (&s[..idx], 1024),
+        Some((idx, 'M' | 'm')) => (&s[..idx], 1024 * 1024),
+        Some((idx, 'G' | 'g')) => (&s[..idx], 1024 * 1024 * 1024),
+        _ => (s, 1),
+    };
+    return num.parse::<u64>().ok()?.checked_mul(multiplier);
+}
@@ -0,0 +1,223 @@
+use rayon::slice::ParallelSliceMut;
+use std::ffi::CString;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Component, Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+// DeleteCmd, the `--delete` action. Failures are counted so they're reflected in the exit status
+pub struct DeleteCmd {
+    pub dry_run: bool,
+    pub force: bool,
+    num_failures: AtomicUsize,
+}
+
+impl DeleteCmd {
+    pub fn new(dry_run: bool, force: bool) -> DeleteCmd {
+        return DeleteCmd { dry_run, force, num_failures: AtomicUsize::new(0) };
+    }
+
+    pub fn num_failures(&self) -> usize {
+        return self.num_failures.load(Ordering::Relaxed);
+    }
+
+    // sort_and_check, sorts the `results` in descending order, which puts every entry before its parent directory, and
+    // refuses to delete the root or (when no pattern or filter was given) every entry under it, unless forced
+    pub fn sort_and_check(&self, root: &Path, results: &mut [(PathBuf, usize)], is_unfiltered: bool) -> std::io::Result<()> {
+        results.par_sort_by(|a, b| {
+            return a.0.as_os_str().as_bytes().cmp(b.0.as_os_str().as_bytes()).reverse();
+        });
+        let num_root_matches = results.iter().filter(|(path, _)| path.components().eq(root.components())).count();
+        if !self.force && num_root_matches > 0 {
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "refusing to `--delete` the root directory, use `--force` to override"));
+        }
+        // Without a pattern or any filter every entry matches, which is almost never intended
+        if !self.force && is_unfiltered {
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "refusing to `--delete` every entry under the root directory (no pattern or filter was given), use `--force` to override"));
+        }
+        return Ok(());
+    }
+
+    // run, deletes the `sorted_desc_results`, which MUST be sorted in descending order so children are deleted before
+    // their parents. Directories that still have (unmatched) entries fail to delete, same as `find -delete`
+    pub fn run(&self, root: &Path, sorted_desc_results: &[(PathBuf, bool)]) {
+        let root_fd = match open_dir_at(libc::AT_FDCWD, root.as_os_str().as_bytes(), false) {
+            Ok(fd) => fd,
+            Err(e) => {
+                eprintln!("error: failed to open '{}' for deleting: {}", root.display(), e);
+                self.num_failures.fetch_add(sorted_desc_results.len(), Ordering::Relaxed);
+                return;
+            }
+        };
+
+        for (path, is_dir) in sorted_desc_results {
+            if let Err(e) = delete_under_root(root_fd, root, path, *is_dir) {
+                eprintln!("error: failed to delete '{}': {}", path.display(), e);
+                self.num_failures.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        unsafe { libc::close(root_fd) };
+    }
+}
+
+// delete_under_root, every directory between the root and the entry is opened relative to its parent without following
+// symlinks, then the entry is removed with `unlinkat`. A directory that's swapped for a symlink mid-run can't redirect
+// the delete outside of the root
+fn delete_under_root(root_fd: libc::c_int, root: &Path, path: &Path, is_dir: bool) -> std::io::Result<()> {
+    let Ok(rest) = path.strip_prefix(root) else {
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "path isn't under the root directory"));
+    };
+    let comps: Vec<Component> = rest.components().collect();
+    if comps.len() == 0 {
+        return delete_root(root);
+    }
+    let Some((Component::Normal(name), parent_comps)) = comps.split_last() else {
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "unexpected path component"));
+    };
+
+    let mut parent_fd = root_fd;
+    for comp in parent_comps {
+        let Component::Normal(comp_name) = comp else {
+            close_if_opened(parent_fd, root_fd);
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "unexpected path component"));
+        };
+        let next_fd = open_dir_at(parent_fd, comp_name.as_bytes(), true);
+        close_if_opened(parent_fd, root_fd);
+        parent_fd = next_fd?;
+    }
+
+    let c_name = CString::new(name.as_bytes())?;
+    let flags = if is_dir { libc::AT_REMOVEDIR } else { 0 };
+    let res = unsafe { libc::unlinkat(parent_fd, c_name.as_ptr(), flags) };
+    let err = std::io::Error::last_os_error();
+    close_if_opened(parent_fd, root_fd);
+    if res != 0 {
+        return Err(err);
+    }
+    return Ok(());
+}
+
+// delete_root, a (forced) delete of the root itself, it's the last entry so it's only deleted once it's empty
+fn delete_root(root: &Path) -> std::io::Result<()> {
+    let Some(name) = root.file_name() else {
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "the root directory has no name to delete it by"));
+    };
+    let parent = match root.parent() {
+        Some(p) if p.as_os_str().len() > 0 => p,
+        _ => Path::new("."),
+    };
+    let parent_fd = open_dir_at(libc::AT_FDCWD, parent.as_os_str().as_bytes(), false)?;
+    let c_name = CString::new(name.as_bytes())?;
+    let res = unsafe { libc::unlinkat(parent_fd, c_name.as_ptr(), libc::AT_REMOVEDIR) };
+    let err = std::io::Error::last_os_error();
+    unsafe { libc::close(parent_fd) };
+    if res != 0 {
+        return Err(err);
+    }
+    return Ok(());
+}
+
+fn open_dir_at(dir_fd: libc::c_int, name: &[u8], no_follow: bool) -> std::io::Result<libc::c_int> {
+    let c_name = CString::new(name)?;
+    let mut flags = libc::O_RDONLY | libc::O_DIRECTORY | libc::O_CLOEXEC;
+    if no_follow {
+        flags |= libc::O_NOFOLLOW;
+    }
+    let fd = unsafe { libc::openat(dir_fd, c_name.as_ptr(), flags) };
+    if fd < 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    return Ok(fd);
+}
+
+fn close_if_opened(fd: libc::c_int, root_fd: libc::c_int) {
+    if fd != root_fd {
+        unsafe { libc::close(fd) };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FT_FILE: usize = 0;
+    const FT_DIR: usize = 2;
+
+    // temp_root, an empty directory that's unique to the test
+    fn temp_root(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("pff_delete_test_{}_{}", std::process::id(), name));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        return dir;
+    }
+
+    fn to_entries(results: &[(PathBuf, usize)]) -> Vec<(PathBuf, bool)> {
+        return results.iter().map(|(path, category)| (path.clone(), category % 3 == FT_DIR)).collect();
+    }
+
+    #[test]
+    fn children_are_deleted_before_parents() {
+        let root = temp_root("order");
+        std::fs::create_dir_all(root.join("a").join("b")).unwrap();
+        std::fs::write(root.join("a").join("b").join("c"), b"").unwrap();
+        std::fs::write(root.join("a").join("b.txt"), b"").unwrap();
+        let mut results = vec![
+            (root.join("a"), FT_DIR),
+            (root.join("a").join("b.txt"), FT_FILE),
+            (root.join("a").join("b"), FT_DIR),
+            (root.join("a").join("b").join("c"), FT_FILE),
+        ];
+
+        let delete = DeleteCmd::new(false, false);
+        delete.sort_and_check(&root, &mut results, false).unwrap();
+        for (idx, (path, _)) in results.iter().enumerate() {
+            assert!(results[..idx].iter().all(|(prev, _)| !path.starts_with(prev)), "'{}' is sorted after its parent", path.display());
+        }
+
+        // A directory that's deleted before its entries fails, as it isn't empty yet
+        delete.run(&root, &to_entries(&results));
+        assert_eq!(delete.num_failures(), 0);
+        assert!(!root.join("a").exists());
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn symlinks_are_removed_not_followed() {
+        let root = temp_root("symlink");
+        let outside = temp_root("symlink_outside");
+        std::fs::write(outside.join("keep"), b"").unwrap();
+        std::os::unix::fs::symlink(&outside, root.join("link")).unwrap();
+
+        // A symlinked directory in the middle of a path isn't traversed
+        let delete = DeleteCmd::new(false, false);
+        delete.run(&root, &[(root.join("link").join("keep"), false)]);
+        assert_eq!(delete.num_failures(), 1);
+        assert!(outside.join("keep").exists());
+
+        // The symlink itself is removed, not what it points to
+        delete.run(&root, &[(root.join("link"), false)]);
+        assert_eq!(delete.num_failures(), 1);
+        assert!(std::fs::symlink_metadata(root.join("link")).is_err());
+        assert!(outside.join("keep").exists());
+
+        std::fs::remove_dir_all(&root).unwrap();
+        std::fs::remove_dir_all(&outside).unwrap();
+    }
+
+    #[test]
+    fn root_and_unfiltered_deletes_are_refused_unless_forced() {
+        let root = PathBuf::from("/tmp/pff_delete_test_root");
+        let mut results = vec![(root.join("a"), FT_FILE), (root.clone(), FT_DIR)];
+        assert!(DeleteCmd::new(false, false).sort_and_check(&root, &mut results, false).is_err());
+        assert!(DeleteCmd::new(true, false).sort_and_check(&root, &mut results, false).is_err());
+        assert!(DeleteCmd::new(false, true).sort_and_check(&root, &mut results, false).is_ok());
+
+        let mut results = vec![(root.join("a"), FT_FILE)];
+        assert!(DeleteCmd::new(false, false).sort_and_check(&root, &mut results, false).is_ok());
+        assert!(DeleteCmd::new(false, false).sort_and_check(&root, &mut results, true).is_err());
+        assert!(DeleteCmd::new(false, true).sort_and_check(&root, &mut results, true).is_ok());
+
+        // A trailing '/' (as matched directories have) still matches the root
+        let mut results = vec![(PathBuf::from("/tmp/pff_delete_test_root/"), FT_DIR)];
+        assert!(DeleteCmd::new(false, false).sort_and_check(&root, &mut results, false).is_err());
+    }
+}
@@ -0,0 +1,207 @@
+use rayon::iter::IntoParallelRefIterator;
+use rayon::iter::ParallelIterator;
+use std::ffi::{OsStr, OsString};
+use std::io::Write;
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+// Headroom left below `ARG_MAX` for anything else the kernel counts
+const ARG_MAX_HEADROOM: usize = 2048;
+const FALLBACK_ARG_MAX: usize = 128 * 1024;
+
+pub const PLACEHOLDER_HELP: &str = "{} path, {/} basename, {//} parent dir, {.} path without extension, {/.} stem";
+
+#[derive(Clone, Copy, PartialEq)]
+enum Placeholder {
+    Path,
+    BaseName,
+    ParentDir,
+    NoExtension,
+    Stem,
+}
+
+// ExecArg, each argument of the command is either literal text or contains placeholders (e.g. 'out/{/.}.txt')
+enum ExecArg {
+    Literal(OsString),
+    Template(Vec<(Vec<u8>, Option<Placeholder>)>),
+}
+
+// ExecCmd, a `--exec` or `--exec-batch` command, compiled once. Failures are counted so they're reflected in the exit status
+pub struct ExecCmd {
+    program: OsString,
+    args: Vec<ExecArg>,
+    is_batch: bool,
+    num_failures: AtomicUsize,
+}
+
+impl ExecCmd {
+    // new, a command with no placeholders has the path appended to it (same as `fd`)
+    pub fn new(cmd: &[String], is_batch: bool) -> Result<ExecCmd, String> {
+        if cmd.len() == 0 {
+            return Err(String::from("missing command for `--exec`/`--exec-batch`"));
+        }
+
+        let mut args: Vec<ExecArg> = cmd[1..].iter().map(|a| parse_arg(a)).collect();
+        let has_placeholder = args.iter().any(|a| matches!(a, ExecArg::Template(_)));
+        if !has_placeholder {
+            args.push(ExecArg::Template(vec![(Vec::new(), Some(Placeholder::Path))]));
+        }
+        if is_batch {
+            // Batches pass every path as a separate argument, so a placeholder must be an argument on its own
+            let num_placeholders = args.iter().filter(|a| matches!(a, ExecArg::Template(_))).count();
+            let is_standalone_path = args.iter().any(|a| match a {
+                ExecArg::Template(parts) => parts.len() == 1 && parts[0].0.len() == 0,
+                _ => false,
+            });
+            if num_placeholders > 1 || !is_standalone_path {
+                return Err(String::from("`--exec-batch` commands can only contain a single placeholder, as a separate argument"));
+            }
+        }
+        return Ok(ExecCmd { program: OsString::from(&cmd[0]), args, is_batch, num_failures: AtomicUsize::new(0) });
+    }
+
+    pub fn num_failures(&self) -> usize {
+        return self.num_failures.load(Ordering::Relaxed);
+    }
+
+    // run, runs the command for the `paths`, either once per path (in parallel across the thread pool) or in as few
+    // batches as `ARG_MAX` allows
+    pub fn run(&self, paths: &[PathBuf]) {
+        if !self.is_batch {
+            paths.par_iter().for_each(|p| {
+                let mut cmd = Command::new(&self.program);
+                for a in &self.args {
+                    cmd.arg(expand_arg(a, p));
+                }
+                self.run_command(cmd);
+            });
+            return;
+        }
+
+        let fixed_args_len: usize = self.program.len() + 1 + self.args.iter().map(|a| match a {
+            ExecArg::Literal(l) => l.len() + 1 + size_of::<usize>(),
+            ExecArg::Template(_) => 0,
+        }).sum::<usize>();
+        let env_len: usize = std::env::vars_os().map(|(k, v)| k.len() + v.len() + 2 + size_of::<usize>()).sum();
+        let max_batch_len = arg_max().saturating_sub(fixed_args_len + env_len + ARG_MAX_HEADROOM);
+
+        // `new` makes sure a batch command has exactly one placeholder argument, each path is expanded into it (e.g. `{/}`
+        // passes the basenames)
+        let Some(template) = self.args.iter().find(|a| matches!(a, ExecArg::Template(_))) else {
+            return;
+        };
+        let mut batch_start = 0;
+        while batch_start < paths.len() {
+            let mut batch_args: Vec<OsString> = Vec::new();
+            let mut batch_len = 0;
+            let mut batch_end = batch_start;
+            while batch_end < paths.len() {
+                let arg = expand_arg(template, &paths[batch_end]);
+                let arg_len = arg.len() + 1 + size_of::<usize>();
+                if batch_end > batch_start && batch_len + arg_len > max_batch_len {
+                    break;
+                }
+                batch_args.push(arg);
+                batch_len += arg_len;
+                batch_end += 1;
+            }
+
+            let mut cmd = Command::new(&self.program);
+            for a in &self.args {
+                match a {
+                    ExecArg::Literal(l) => { cmd.arg(l); }
+                    ExecArg::Template(_) => { cmd.args(&batch_args); }
+                }
+            }
+            self.run_command(cmd);
+            batch_start = batch_end;
+        }
+    }
+
+    // run_command, the output of each command is captured then written in one go, so the output of commands running
+    // in parallel isn't interleaved
+    fn run_command(&self, mut cmd: Command) {
+        match cmd.output() {
+            Ok(output) => {
+                if output.stdout.len() > 0 {
+                    let _ = std::io::stdout().lock().write_all(&output.stdout);
+                }
+                if output.stderr.len() > 0 {
+                    let _ = std::io::stderr().lock().write_all(&output.stderr);
+                }
+                if !output.status.success() {
+                    self.num_failures.fetch_add(1, Ordering::Relaxed);
+                }
+            }
+            Err(e) => {
+                eprintln!("error: failed to run '{}': {}", self.program.to_string_lossy(), e);
+                self.num_failures.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+}
+
+fn parse_arg(arg: &str) -> ExecArg {
+    let placeholders = [("{//}", Placeholder::ParentDir), ("{/.}", Placeholder::Stem), ("{/}", Placeholder::BaseName), ("{.}", Placeholder::NoExtension), ("{}", Placeholder::Path)];
+    let mut parts: Vec<(Vec<u8>, Option<Placeholder>)> = Vec::new();
+    let mut rest = arg;
+    let mut literal: Vec<u8> = Vec::new();
+    'outer: while rest.len() > 0 {
+        for (token, placeholder) in placeholders {
+            if rest.starts_with(token) {
+                parts.push((std::mem::take(&mut literal), Some(placeholder)));
+                rest = &rest[token.len()..];
+                continue 'outer;
+            }
+        }
+        let c = rest.chars().next().unwrap();
+        let mut buf = [0; 4];
+        literal.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+        rest = &rest[c.len_utf8()..];
+    }
+
+    if parts.len() == 0 {
+        return ExecArg::Literal(OsString::from(arg));
+    }
+    if literal.len() > 0 {
+        parts.push((literal, None));
+    }
+    return ExecArg::Template(parts);
+}
+
+fn expand_arg(arg: &ExecArg, path: &Path) -> OsString {
+    let parts = match arg {
+        ExecArg::Literal(l) => return l.clone(),
+        ExecArg::Template(parts) => parts,
+    };
+
+    let mut ret: Vec<u8> = Vec::new();
+    for (literal, placeholder) in parts {
+        ret.extend_from_slice(literal);
+        let expanded: &OsStr = match placeholder {
+            None => continue,
+            Some(Placeholder::Path) => path.as_os_str(),
+            Some(Placeholder::BaseName) => path.file_name().unwrap_or_default(),
+            Some(Placeholder::ParentDir) => path.parent().unwrap_or(Path::new("")).as_os_str(),
+            Some(Placeholder::Stem) => path.file_stem().unwrap_or_default(),
+            Some(Placeholder::NoExtension) => {
+                let path_bytes = path.as_os_str().as_bytes();
+                let trimmed = path_bytes.strip_suffix(b"/").unwrap_or(path_bytes);
+                let ext_len = path.extension().map_or(0, |e| e.len() + 1);
+                OsStr::from_bytes(&trimmed[..trimmed.len() - ext_len])
+            }
+        };
+        ret.extend_from_slice(expanded.as_bytes());
+    }
+    return OsString::from_vec(ret);
+}
+
+fn arg_max() -> usize {
+    let ret = unsafe { libc::sysconf(libc::_SC_ARG_MAX) };
+    if ret <= 0 {
+        return FALLBACK_ARG_MAX;
+    }
+    return ret as usize;
+}
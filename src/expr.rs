@@ -0,0 +1,435 @@
+use regex::bytes::Regex;
+use std::cell::OnceCell;
+use std::ffi::OsStr;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::{FileTypeExt, MetadataExt};
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const SECS_PER_DAY: i64 = 24 * 60 * 60;
+
+#[derive(Clone, Copy)]
+pub enum Cmp {
+    Less,
+    Equal,
+    Greater,
+}
+
+// Expr, a predicate tree compiled from a subset of `find`'s expression syntax
+pub enum Expr {
+    True,
+    Name(Regex),
+    Type(Vec<u8>),
+    Size(Cmp, u64, u64), // comparison, amount, unit size (in bytes)
+    Mtime(Cmp, i64, i64), // comparison, days, when the search started (in seconds)
+    Prune,
+    Print,
+    Not(Box<Expr>),
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+}
+
+// EvalCtx, the entry an expression is evaluated against, its metadata is only read if a predicate needs it
+pub struct EvalCtx<'a> {
+    pub path: &'a Path,
+    pub name: &'a OsStr,
+    pub file_type: std::fs::FileType,
+    metadata: OnceCell<Option<std::fs::Metadata>>,
+}
+
+// Eval, the outcome of evaluating an expression for an entry. With an explicit `-print` only printed entries match
+pub struct Eval {
+    pub is_match: bool,
+    pub is_pruned: bool,
+}
+
+impl<'a> EvalCtx<'a> {
+    pub fn new(path: &'a Path, name: &'a OsStr, file_type: std::fs::FileType) -> EvalCtx<'a> {
+        return EvalCtx { path, name, file_type, metadata: OnceCell::new() };
+    }
+
+    fn metadata(&self) -> Option<&std::fs::Metadata> {
+        return self.metadata.get_or_init(|| std::fs::symlink_metadata(self.path).ok()).as_ref();
+    }
+}
+
+impl Expr {
+    // compile, operators bind (tightest first): '!', '-a' (or nothing), '-o', the same as `find`
+    pub fn compile(tokens: &[String]) -> Result<Expr, String> {
+        if tokens.len() == 0 {
+            return Ok(Expr::True);
+        }
+        let start_secs = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs() as i64);
+        let mut parser = Parser { tokens, pos: 0, start_secs };
+        let ret = parser.parse_or()?;
+        if parser.pos < tokens.len() {
+            return Err(format!("unexpected '{}' in `--find` expression", tokens[parser.pos]));
+        }
+        return Ok(ret);
+    }
+
+    pub fn eval(&self, ctx: &EvalCtx) -> Eval {
+        let mut ret = Eval { is_match: false, is_pruned: false };
+        let mut is_printed = false;
+        let result = self.eval_node(ctx, &mut ret.is_pruned, &mut is_printed);
+        ret.is_match = if self.has_print() { is_printed } else { result };
+        return ret;
+    }
+
+    fn eval_node(&self, ctx: &EvalCtx, is_pruned: &mut bool, is_printed: &mut bool) -> bool {
+        match self {
+            Expr::True => true,
+            Expr::Name(rx) => rx.is_match(ctx.name.as_bytes()),
+            Expr::Type(types) => types.contains(&type_char(&ctx.file_type)),
+            Expr::Size(cmp, amount, unit) => {
+                let Some(md) = ctx.metadata() else { return false };
+                // Sizes are rounded up to the unit, so `-size -1M` only matches empty files (same as `find`)
+                compare(*cmp, md.size().div_ceil(*unit), *amount)
+            }
+            Expr::Mtime(cmp, days, start_secs) => {
+                let Some(md) = ctx.metadata() else { return false };
+                // Ages are in whole days, any fraction is ignored (same as `find`)
+                let age_days = (start_secs - md.mtime()).div_euclid(SECS_PER_DAY);
+                compare(*cmp, age_days, *days)
+            }
+            Expr::Prune => {
+                *is_pruned = ctx.file_type.is_dir();
+                true
+            }
+            Expr::Print => {
+                *is_printed = true;
+                true
+            }
+            Expr::Not(e) => !e.eval_node(ctx, is_pruned, is_printed),
+            Expr::And(l, r) => l.eval_node(ctx, is_pruned, is_printed) && r.eval_node(ctx, is_pruned, is_printed),
+            Expr::Or(l, r) => l.eval_node(ctx, is_pruned, is_printed) || r.eval_node(ctx, is_pruned, is_printed),
+        }
+    }
+
+    fn has_print(&self) -> bool {
+        match self {
+            Expr::Print => true,
+            Expr::Not(e) => e.has_print(),
+            Expr::And(l, r) | Expr::Or(l, r) => l.has_print() || r.has_print(),
+            _ => false,
+        }
+    }
+}
+
+struct Parser<'a> {
+    tokens: &'a [String],
+    pos: usize,
+    start_secs: i64,
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> Option<&'a str> {
+        return self.tokens.get(self.pos).map(|t| t.as_str());
+    }
+
+    fn next_arg(&mut self, primary: &str) -> Result<&'a str, String> {
+        let Some(arg) = self.tokens.get(self.pos) else {
+            return Err(format!("missing argument for '{}' in `--find` expression", primary));
+        };
+        self.pos += 1;
+        return Ok(arg);
+    }
+
+    fn parse_or(&mut self) -> Result<Expr, String> {
+        let mut ret = self.parse_and()?;
+        while matches!(self.peek(), Some("-o" | "-or")) {
+            self.pos += 1;
+            ret = Expr::Or(Box::new(ret), Box::new(self.parse_and()?));
+        }
+        return Ok(ret);
+    }
+
+    fn parse_and(&mut self) -> Result<Expr, String> {
+        let mut ret = self.parse_not()?;
+        loop {
+            match self.peek() {
+                None | Some("-o" | "-or" | ")") => break,
+                Some("-a" | "-and") => { self.pos += 1; }
+                _ => {}
+            }
+            ret = Expr::And(Box::new(ret), Box::new(self.parse_not()?));
+        }
+        return Ok(ret);
+    }
+
+    fn parse_not(&mut self) -> Result<Expr, String> {
+        if matches!(self.peek(), Some("!" | "-not")) {
+            self.pos += 1;
+            return Ok(Expr::Not(Box::new(self.parse_not()?)));
+        }
+        return self.parse_primary();
+    }
+
+    fn parse_primary(&mut self) -> Result<Expr, String> {
+        let Some(token) = self.peek() else {
+            return Err(String::from("incomplete `--find` expression"));
+        };
+        self.pos += 1;
+        match token {
+            "(" => {
+                let ret = self.parse_or()?;
+                if self.peek() != Some(")") {
+                    return Err(String::from("missing ')' in `--find` expression"));
+                }
+                self.pos += 1;
+                return Ok(ret);
+            }
+            "-name" | "-iname" => {
+                let glob = self.next_arg(token)?;
+                // Names aren't necessarily UTF-8 (or free of newlines), so the regex matches bytes
+                let rx_str = format!("(?{}s-u)^{}$", if token == "-iname" { "i" } else { "" }, glob_to_regex(glob));
+                return Regex::new(&rx_str).map(Expr::Name).map_err(|_| format!("invalid pattern '{}' for '{}'", glob, token));
+            }
+            "-type" => {
+                let arg = self.next_arg(token)?;
+                let mut types = Vec::new();
+                for t in arg.split(',') {
+                    if !matches!(t, "f" | "d" | "l" | "p" | "s" | "b" | "c") {
+                        return Err(format!("invalid type '{}' for '-type', must be one of: f, d, l, p, s, b, c", t));
+                    }
+                    types.push(t.as_bytes()[0]);
+                }
+                return Ok(Expr::Type(types));
+            }
+            "-size" => {
+                let arg = self.next_arg(token)?;
+                let (cmp, rest) = parse_cmp(arg);
+                let (amount, unit) = match rest.char_indices().last() {
+                    Some((idx, c)) if c.is_ascii_alphabetic() => (&rest[..idx], c),
+                    _ => (rest, 'b'),
+                };
+                let unit_size = match unit {
+                    'c' => 1,
+                    'w' => 2,
+                    'b' => 512,
+                    'k' => 1024,
+                    'M' => 1024 * 1024,
+                    'G' => 1024 * 1024 * 1024,
+                    _ => return Err(format!("invalid unit '{}' for '-size', must be one of: c, w, b, k, M, G", unit)),
+                };
+                let Ok(amount) = amount.parse() else {
+                    return Err(format!("invalid size '{}' for '-size'", arg));
+                };
+                return Ok(Expr::Size(cmp, amount, unit_size));
+            }
+            "-mtime" => {
+                let arg = self.next_arg(token)?;
+                let (cmp, rest) = parse_cmp(arg);
+                let Ok(days) = rest.parse() else {
+                    return Err(format!("invalid number of days '{}' for '-mtime'", arg));
+                };
+                return Ok(Expr::Mtime(cmp, days, self.start_secs));
+            }
+            "-prune" => Ok(Expr::Prune),
+            "-print" => Ok(Expr::Print),
+            _ => Err(format!("unsupported '{}' in `--find` expression, expected one of: -name, -iname, -type, -size, -mtime, -prune, -print, -o, -a, !, (, )", token)),
+        }
+    }
+}
+
+// parse_cmp, a leading '+' means greater than and '-' less than, otherwise it's exactly equal
+fn parse_cmp(arg: &str) -> (Cmp, &str) {
+    if let Some(rest) = arg.strip_prefix('+') {
+        return (Cmp::Greater, rest);
+    } else if let Some(rest) = arg.strip_prefix('-') {
+        return (Cmp::Less, rest);
+    }
+    return (Cmp::Equal, arg);
+}
+
+fn compare<T: Ord>(cmp: Cmp, value: T, amount: T) -> bool {
+    match cmp {
+        Cmp::Less => value < amount,
+        Cmp::Equal => value == amount,
+        Cmp::Greater => value > amount,
+    }
+}
+
+// glob_to_regex, converts a shell glob ('*', '?', '[...]' and '\' escapes) to an (unanchored) regex, for a byte matching
+// (`(?s-u)`) regex. '?' matches a single UTF-8 character or any other byte, non-ASCII literals and classes are matched
+// as Unicode (so `-iname` folds their case)
+fn glob_to_regex(glob: &str) -> String {
+    let chars: Vec<char> = glob.chars().collect();
+    let mut ret = String::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        i += 1;
+        match c {
+            '*' => ret.push_str(".*"),
+            '?' => ret.push_str("(?:(?u:.)|.)"),
+            '\\' if i < chars.len() => {
+                push_unicode_aware(&mut ret, &regex::escape(&chars[i].to_string()));
+                i += 1;
+            }
+            '[' => {
+                // A ']' straight after the '[' (or its negation) is part of the class. An unclosed '[' is matched
+                // literally (same as `fnmatch`)
+                let mut class_start = i;
+                if matches!(chars.get(class_start), Some('!' | '^')) {
+                    class_start += 1;
+                }
+                let Some(len) = chars.iter().skip(class_start + 1).position(|cc| *cc == ']') else {
+                    ret.push_str(&regex::escape("["));
+                    continue;
+                };
+                let class_end = class_start + 1 + len;
+                let mut class = String::from("[");
+                if class_start > i {
+                    class.push('^');
+                }
+                for cc in &chars[class_start..class_end] {
+                    if matches!(cc, '\\' | '[' | ']' | '^' | '&' | '~') {
+                        class.push('\\');
+                    }
+                    class.push(*cc);
+                }
+                class.push(']');
+                push_unicode_aware(&mut ret, &class);
+                i = class_end + 1;
+            }
+            _ => push_unicode_aware(&mut ret, &regex::escape(&c.to_string())),
+        }
+    }
+    return ret;
+}
+
+// push_unicode_aware, non-ASCII characters aren't allowed in byte classes, so anything containing them is matched as Unicode
+fn push_unicode_aware(out: &mut String, rx: &str) {
+    if rx.is_ascii() {
+        out.push_str(rx);
+        return;
+    }
+    out.push_str(&format!("(?u:{})", rx));
+}
+
+fn type_char(ft: &std::fs::FileType) -> u8 {
+    if ft.is_symlink() {
+        return b'l';
+    } else if ft.is_dir() {
+        return b'd';
+    } else if ft.is_fifo() {
+        return b'p';
+    } else if ft.is_socket() {
+        return b's';
+    } else if ft.is_block_device() {
+        return b'b';
+    } else if ft.is_char_device() {
+        return b'c';
+    }
+    return b'f';
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn compile(expr: &str) -> Expr {
+        let tokens: Vec<String> = expr.split_whitespace().map(String::from).collect();
+        return Expr::compile(&tokens).unwrap();
+    }
+
+    fn name_matches(glob: &str, name: &[u8]) -> bool {
+        let Expr::Name(rx) = compile(&format!("-name {}", glob)) else { panic!("expected a name predicate") };
+        return rx.is_match(name);
+    }
+
+    // eval_names, evaluates `expr` against a file and a directory (in that order) with the same `name`
+    fn eval_names(expr: &str, name: &str) -> [Eval; 2] {
+        let dir = std::env::temp_dir().join(format!("pff_expr_test_{}_{}", std::process::id(), name));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(dir.join("d").join(name)).unwrap();
+        std::fs::create_dir_all(dir.join("f")).unwrap();
+        std::fs::write(dir.join("f").join(name), b"").unwrap();
+        let expr = compile(expr);
+        let ret = ["f", "d"].map(|kind| {
+            let path = dir.join(kind).join(name);
+            let ft = std::fs::symlink_metadata(&path).unwrap().file_type();
+            return expr.eval(&EvalCtx::new(&path, OsStr::new(name), ft));
+        });
+        std::fs::remove_dir_all(&dir).unwrap();
+        return ret;
+    }
+
+    #[test]
+    fn and_binds_tighter_than_or() {
+        // '-name a -o -name b -type d' is 'a OR (b AND dir)'
+        let [file, dir] = eval_names("-name a -o -name b -type d", "a");
+        assert!(file.is_match && dir.is_match);
+        let [file, dir] = eval_names("-name a -o -name b -type d", "b");
+        assert!(!file.is_match && dir.is_match);
+        let [file, dir] = eval_names("( -name a -o -name b ) -type d", "a");
+        assert!(!file.is_match && dir.is_match);
+    }
+
+    #[test]
+    fn not_binds_tightest() {
+        let [file, dir] = eval_names("! -name a -type d", "b");
+        assert!(!file.is_match && dir.is_match);
+        let [file, dir] = eval_names("! ( -name b -type d )", "b");
+        assert!(file.is_match && !dir.is_match);
+    }
+
+    #[test]
+    fn prune_only_prunes_directories() {
+        let [file, dir] = eval_names("-name skip -prune -o -type f", "skip");
+        assert!(!file.is_pruned && file.is_match);
+        assert!(dir.is_pruned && dir.is_match);
+        let [file, dir] = eval_names("-name skip -prune -o -type f", "keep");
+        assert!(!file.is_pruned && file.is_match);
+        assert!(!dir.is_pruned && !dir.is_match);
+    }
+
+    #[test]
+    fn explicit_print_only_matches_printed_entries() {
+        // The pruning branch is true, but without its own '-print' nothing it matches is output
+        let [file, dir] = eval_names("-name skip -prune -o -type f -print", "skip");
+        assert!(!file.is_match && !dir.is_match && dir.is_pruned);
+        let [file, dir] = eval_names("-name skip -prune -o -type f -print", "keep");
+        assert!(file.is_match && !dir.is_match && !dir.is_pruned);
+        let [file, dir] = eval_names("-type d -print -o -print", "any");
+        assert!(file.is_match && dir.is_match);
+    }
+
+    #[test]
+    fn glob_wildcards_match_any_byte() {
+        assert!(name_matches("*.rs", b"main.rs"));
+        assert!(!name_matches("*.rs", b"main.rsx"));
+        assert!(name_matches("a*b", b"a\nb"));
+        assert!(name_matches("a*b", b"a\xff\xfeb"));
+        assert!(name_matches("a?b", b"a\xffb"));
+        assert!(name_matches("a?b", "aéb".as_bytes()));
+        assert!(!name_matches("a?b", b"ab"));
+    }
+
+    #[test]
+    fn glob_classes() {
+        assert!(name_matches("[abc].txt", b"b.txt"));
+        assert!(!name_matches("[abc].txt", b"d.txt"));
+        assert!(name_matches("[a-c]", b"b"));
+        assert!(name_matches("[!a-c]", b"d"));
+        assert!(!name_matches("[!a-c]", b"b"));
+        assert!(name_matches("[^a-c]", b"d"));
+        assert!(name_matches("[]]", b"]"));
+        assert!(name_matches("[!]]", b"a"));
+        assert!(!name_matches("[!]]", b"]"));
+        assert!(name_matches("[é]", "é".as_bytes()));
+        // An unclosed '[' is literal
+        assert!(name_matches("a[b", b"a[b"));
+        assert!(name_matches("\\*", b"*"));
+        assert!(!name_matches("\\*", b"a"));
+    }
+
+    #[test]
+    fn iname_ignores_case() {
+        let Expr::Name(rx) = compile("-iname *.RS") else { panic!("expected a name predicate") };
+        assert!(rx.is_match(b"main.rs"));
+        let Expr::Name(rx) = compile("-iname É") else { panic!("expected a name predicate") };
+        assert!(rx.is_match("é".as_bytes()));
+    }
+}
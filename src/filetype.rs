@@ -0,0 +1,100 @@
+// BUILTIN_TYPES, the named groups for `--type`, a glob is either '*.<extension>' or an exact file name
+pub const BUILTIN_TYPES: &[(&str, &[&str])] = &[
+    ("archive", &["*.7z", "*.bz2", "*.gz", "*.rar", "*.tar", "*.tgz", "*.xz", "*.zip", "*.zst"]),
+    ("audio", &["*.aac", "*.flac", "*.m4a", "*.mp3", "*.ogg", "*.opus", "*.wav"]),
+    ("c", &["*.c", "*.h"]),
+    ("cpp", &["*.c++", "*.cc", "*.cpp", "*.cxx", "*.h", "*.h++", "*.hh", "*.hpp", "*.hxx", "*.inl"]),
+    ("css", &["*.css", "*.less", "*.sass", "*.scss"]),
+    ("go", &["*.go"]),
+    ("html", &["*.htm", "*.html"]),
+    ("image", &["*.bmp", "*.gif", "*.ico", "*.jpeg", "*.jpg", "*.png", "*.svg", "*.tif", "*.tiff", "*.webp"]),
+    ("java", &["*.java"]),
+    ("js", &["*.cjs", "*.js", "*.jsx", "*.mjs"]),
+    ("json", &["*.json"]),
+    ("make", &["*.mk", "GNUmakefile", "Makefile", "makefile"]),
+    ("md", &["*.markdown", "*.md"]),
+    ("py", &["*.py", "*.pyi"]),
+    ("rust", &["*.rs"]),
+    ("sh", &["*.bash", "*.sh", "*.zsh"]),
+    ("toml", &["*.toml"]),
+    ("ts", &["*.cts", "*.mts", "*.ts", "*.tsx"]),
+    ("video", &["*.avi", "*.mkv", "*.mov", "*.mp4", "*.webm"]),
+    ("yaml", &["*.yaml", "*.yml"]),
+];
+
+// NameFilter, the extensions (matched case insensitively, stored lowercase with their '.') and exact names that an
+// entry's basename must have one of, checked before (and instead of) any regex
+#[derive(Default)]
+pub struct NameFilter {
+    pub suffixes: Vec<Vec<u8>>,
+    pub names: Vec<Vec<u8>>,
+}
+
+impl NameFilter {
+    pub fn add_extension(&mut self, ext: &str) {
+        let suffix = format!(".{}", ext.trim_start_matches('.')).to_ascii_lowercase().into_bytes();
+        if !self.suffixes.contains(&suffix) {
+            self.suffixes.push(suffix);
+        }
+    }
+
+    // add_glob, only '*.<extension>' and exact names are supported (the same as the built-in table)
+    pub fn add_glob(&mut self, glob: &str) -> Result<(), String> {
+        if let Some(ext) = glob.strip_prefix("*.") {
+            if ext.len() == 0 || ext.contains(['*', '?', '[', '/']) {
+                return Err(format!("invalid glob '{}', must be '*.<extension>' or a file name", glob));
+            }
+            self.add_extension(ext);
+        } else {
+            if glob.len() == 0 || glob.contains(['*', '?', '[', '/']) {
+                return Err(format!("invalid glob '{}', must be '*.<extension>' or a file name", glob));
+            }
+            self.names.push(glob.as_bytes().to_vec());
+        }
+        return Ok(());
+    }
+
+    // is_match, the name has to be longer than the suffix, so e.g. '.rs' itself isn't a Rust file
+    pub fn is_match(&self, base_name: &[u8]) -> bool {
+        for suffix in &self.suffixes {
+            if base_name.len() > suffix.len() && base_name[base_name.len() - suffix.len()..].eq_ignore_ascii_case(suffix) {
+                return true;
+            }
+        }
+        return self.names.iter().any(|n| n == base_name);
+    }
+}
+
+// parse_type_add, parses '<name>:<glob>[,<glob>...]' for `--type-add`
+pub fn parse_type_add(spec: &str) -> Result<(String, Vec<String>), String> {
+    let Some((name, globs)) = spec.split_once(':') else {
+        return Err(format!("invalid `--type-add` argument '{}', must be '<name>:<glob>[,<glob>...]'", spec));
+    };
+    let globs: Vec<String> = globs.split(',').filter(|g| g.len() > 0).map(String::from).collect();
+    if name.len() == 0 || globs.len() == 0 {
+        return Err(format!("invalid `--type-add` argument '{}', must be '<name>:<glob>[,<glob>...]'", spec));
+    }
+    return Ok((name.to_string(), globs));
+}
+
+// resolve_types, builds the filter for the `--type` names, user defined (`--type-add`) globs extend a built-in type
+// of the same name
+pub fn resolve_types(filter: &mut NameFilter, type_names: &[String], type_adds: &[(String, Vec<String>)]) -> Result<(), String> {
+    for type_name in type_names {
+        let builtin = BUILTIN_TYPES.iter().find(|(name, _)| name == type_name);
+        let added: Vec<&String> = type_adds.iter().filter(|(name, _)| name == type_name).flat_map(|(_, globs)| globs).collect();
+        if builtin.is_none() && added.len() == 0 {
+            let names: Vec<&str> = BUILTIN_TYPES.iter().map(|(name, _)| *name).collect();
+            return Err(format!("unknown type '{}', must be one of: {} (or added with `--type-add`)", type_name, names.join(", ")));
+        }
+        if let Some((_, globs)) = builtin {
+            for glob in globs.iter() {
+                filter.add_glob(glob)?;
+            }
+        }
+        for glob in added {
+            filter.add_glob(glob)?;
+        }
+    }
+    return Ok(());
+}
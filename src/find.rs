@@ -1,69 +1,179 @@
+use rayon::iter::IntoParallelIterator;
 use rayon::iter::IntoParallelRefMutIterator;
 use rayon::iter::ParallelIterator;
-use rayon::slice::ParallelSliceMut;
-use regex::bytes::Regex;
-use std::io::{Error, Write};
+use rayon::slice::{ParallelSlice, ParallelSliceMut};
+use std::collections::HashSet;
+use std::io::Error;
+use std::os::unix::ffi::OsStrExt;
 use std::path::PathBuf;
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
-use crate::label;
+use crate::fuzzy;
+use crate::index;
 use crate::matches;
+use crate::mounts::DeviceFilter;
+use crate::output::{Renderer, Sink};
+use crate::snapshot;
+use crate::stats::{self, RunStats};
+use crate::tree;
 use crate::walk;
 use crate::Config;
 
 const FIRST_WALK_FDL: usize = 256;
+// How long after the `--timeout` deadline a walk is waited for, it's only still running if a read is stuck
+const WATCHDOG_GRACE: Duration = Duration::from_secs(1);
+// The number of indexed directories matched by each task when searching an index
+const INDEX_CHUNK_DIRS: usize = 64;
 
 const FT_FILE: usize = 0;
 const FT_SYMLINK: usize = 1;
 const FT_DIR: usize = 2;
 
-pub fn find(target: String, root: std::path::PathBuf, cfg: &Config) -> Result<Vec<String>, Error> {
+// ThreadWalkResult, what's left of a walk once its matches have been filtered and printed (or kept for sorting)
+struct ThreadWalkResult {
+    paths_to_distribute: Vec<PathBuf>,
+    sorted_results: Vec<(PathBuf, usize)>,
+    walked_dirs: Vec<walk::WalkedDir>,
+    match_counts: [usize; matches::NUM_FILE_CATEGORIES],
+    num_matches: usize,
+    thread_idx: Option<usize>,
+    stats: walk::WalkStats,
+}
+
+// TreeWalk, everything a (parallel) walk of a tree collected once it's finished, or so far. `num_pending_dirs` are the
+// directories that haven't been read yet, the ones handed to the round that's in progress and those its finished threads
+// found for the next round
+pub struct TreeWalk {
+    pub flat_results: Vec<(PathBuf, usize)>,
+    pub walked_dirs: Vec<walk::WalkedDir>,
+    pub match_counts: [usize; matches::NUM_FILE_CATEGORIES],
+    pub num_unvisited_dirs: Option<usize>,
+    pub run_stats: RunStats,
+    num_pending_dirs: usize,
+}
+
+impl TreeWalk {
+    fn new() -> TreeWalk {
+        return TreeWalk { flat_results: Vec::new(), walked_dirs: Vec::new(), match_counts: [0; matches::NUM_FILE_CATEGORIES], num_unvisited_dirs: None, run_stats: RunStats::new(), num_pending_dirs: 0 };
+    }
+}
+
+// FindResult, the output that's only known once the search is finished (see `collect_output`), the number of matches
+// (the exit status depends on it) and every directory that was read, they're only kept with `--watch`. If `--timeout`
+// expired, the results are partial and `num_unvisited_dirs` weren't read, `num_errors` entries couldn't be read
+pub struct FindResult {
+    pub output: Vec<Vec<u8>>,
+    pub num_matches: usize,
+    pub walked_dirs: Vec<PathBuf>,
+    pub num_unvisited_dirs: Option<usize>,
+    pub num_errors: usize,
+}
+
+pub fn find(target: String, root: std::path::PathBuf, cfg: &Config) -> Result<FindResult, Error> {
+    return find_with_walked_dirs(target, root, cfg, None);
+}
+
+// find_with_walked_dirs, unsorted results are sent to `sink` instead of being printed, when there is one
+pub fn find_with_walked_dirs(target: String, root: std::path::PathBuf, cfg: &Config, sink: Option<Sink>) -> Result<FindResult, Error> {
     if cfg.num_threads < 2 {
-        return Err(std::io::Error::new(std::io::ErrorKind::Other, format!("invalid number of threads, '-t' MUST be >= 2")))
+        return Err(std::io::Error::new(std::io::ErrorKind::Other, "invalid number of threads, '-t' MUST be >= 2"))
+    }
+    let dev_filter = DeviceFilter::new(&root, cfg.one_file_system, &cfg.exclude_fs_types)?;
+    let mut walk_ctx = walk::WalkCtx::new(cfg, &target, dev_filter)?;
+    if let Some(sink) = &sink {
+        walk_ctx = walk_ctx.with_stop_flag(sink.is_closed.clone());
     }
+    let renderer = Renderer::new(cfg, &root, &walk_ctx)?.with_sink(sink);
 
-    // Set variables for regex OR exact match based on config
-    let mut regex_target = Regex::new("").unwrap();
-    let mut exact_match_target = Some(&target);
-    if !cfg.equality_match {
-        let regex_result = Regex::new(&target);
-        if regex_result.is_err() {
-            return Err(std::io::Error::new(std::io::ErrorKind::Other, format!("Failed to compile regex")));
-        }
-        regex_target = regex_result.unwrap();
-        exact_match_target = None;
+    let tree_walk = walk_tree(vec![root.clone()], true, &walk_ctx, &renderer)?;
+    return finish_find(&root, &renderer, &walk_ctx, tree_walk);
+}
+
+// find_with_watchdog, `find` with `--timeout`. The deadline is only checked between directories, so a read that never
+// returns (e.g. on a dead network mount) would block the walk forever. Instead the walk runs on its own thread, and if
+// it hasn't finished shortly after the deadline it's abandoned, and whatever its finished threads found is the result
+pub fn find_with_watchdog(target: String, root: PathBuf, cfg: &'static Config, timeout: Duration) -> Result<FindResult, Error> {
+    if cfg.num_threads < 2 {
+        return Err(std::io::Error::new(std::io::ErrorKind::Other, "invalid number of threads, '-t' MUST be >= 2"))
     }
-    
-    // Find multiple directory paths from `root`, to distribute them between threads later
-    let mut initial_dirs = vec![root.clone()];
-    let maybe_initial_paths = walk::walk_collect_matches_until_limit(&mut initial_dirs, FIRST_WALK_FDL, cfg.label_pos, regex_target.clone(), exact_match_target);
-    let Ok((mut paths_to_distribute, mut categorised_results)) = maybe_initial_paths else {
-        return Err(std::io::Error::new(std::io::ErrorKind::Other, format!("Failed to read root path: {:?}", maybe_initial_paths.err())))
-    };
+    let dev_filter = DeviceFilter::new(&root, cfg.one_file_system, &cfg.exclude_fs_types)?;
+    let walk_ctx = Arc::new(walk::WalkCtx::new(cfg, &target, dev_filter)?);
+    let renderer = Arc::new(Renderer::new(cfg, &root, &walk_ctx)?);
+    let progress = Arc::new(Mutex::new(TreeWalk::new()));
 
-    // Remove the target directory from the results of the initial, ST scan (default behaviour)
-    if !cfg.include_target_in_output {
-        let dir_category_idxs = vec![2, 5];
-        for idx in dir_category_idxs {
-            if categorised_results[idx].len() > 0 {
-                let first_entry = categorised_results[idx][0].clone();
-                let first_path = label::remove_label_from_string(first_entry, cfg.label_pos);
-                let remove_first_entry = PathBuf::from(first_path) == root;
-                if remove_first_entry {
-                    categorised_results[idx] = categorised_results[idx][1..].to_vec();
-                    break;
-                }    
-            }
+    // The walk gets its own pool, a stuck thread in the global one would block the sorting (etc.) of the results
+    let Ok(pool) = rayon::ThreadPoolBuilder::new().build() else {
+        return Err(std::io::Error::new(std::io::ErrorKind::Other, "failed to start the search threads"));
+    };
+    let (tx, rx) = mpsc::channel();
+    let (thread_walk_ctx, thread_renderer, thread_progress, thread_root) = (walk_ctx.clone(), renderer.clone(), progress.clone(), root.clone());
+    std::thread::spawn(move || {
+        let _ = tx.send(pool.install(|| walk_tree_shared(vec![thread_root], true, &thread_walk_ctx, &thread_renderer, &thread_progress)));
+    });
+    match rx.recv_timeout(timeout + WATCHDOG_GRACE) {
+        Ok(walk_result) => walk_result?,
+        Err(RecvTimeoutError::Timeout) => {
+            walk_ctx.stop();
+            let mut tree_walk = progress.lock().unwrap();
+            tree_walk.num_unvisited_dirs = Some(tree_walk.num_pending_dirs);
+        }
+        Err(RecvTimeoutError::Disconnected) => {
+            return Err(std::io::Error::new(std::io::ErrorKind::Other, "the search failed"));
         }
     }
+    let tree_walk = std::mem::replace(&mut *progress.lock().unwrap(), TreeWalk::new());
+    return finish_find(&root, &renderer, &walk_ctx, tree_walk);
+}
 
-    let mut flat_results = filter_elements(cfg,&mut categorised_results).into_iter().flatten().collect();
-    
-    if !cfg.is_sorted {
-        print_walk_results(&flat_results);
+// finish_find, the output of a walk, once it's finished (or abandoned)
+fn finish_find(root: &std::path::Path, renderer: &Renderer, walk_ctx: &walk::WalkCtx, mut tree_walk: TreeWalk) -> Result<FindResult, Error> {
+    let cfg = walk_ctx.cfg;
+    let mut walked_dirs = Vec::new();
+    if cfg.watch {
+        walked_dirs = std::mem::take(&mut tree_walk.walked_dirs).into_iter().map(|wd| wd.path).collect();
+    }
+    let mut run_stats = tree_walk.run_stats;
+    let output = collect_output(cfg, root, renderer, walk_ctx, tree_walk.flat_results, tree_walk.walked_dirs, &tree_walk.match_counts, &mut run_stats)?;
+    if cfg.print_stats {
+        run_stats.print();
+    }
+    return Ok(FindResult { output, num_matches: run_stats.num_matches(), walked_dirs, num_unvisited_dirs: tree_walk.num_unvisited_dirs, num_errors: run_stats.num_errors() });
+}
+
+// walk_tree, walks everything under `initial_dirs` across the thread pool. On the `is_root_walk` there's a single initial
+// dir, the root, it's only matched with `--include-target`
+pub fn walk_tree(initial_dirs: Vec<PathBuf>, is_root_walk: bool, walk_ctx: &walk::WalkCtx, renderer: &Renderer) -> Result<TreeWalk, Error> {
+    let progress = Mutex::new(TreeWalk::new());
+    walk_tree_shared(initial_dirs, is_root_walk, walk_ctx, renderer, &progress)?;
+    return Ok(progress.into_inner().unwrap());
+}
+
+// walk_tree_shared, `walk_tree` that merges what each thread found into `progress`, so it can be taken before the walk
+// finishes (see `find_with_watchdog`)
+fn walk_tree_shared(initial_dirs: Vec<PathBuf>, is_root_walk: bool, walk_ctx: &walk::WalkCtx, renderer: &Renderer, progress: &Mutex<TreeWalk>) -> Result<(), Error> {
+    let cfg = walk_ctx.cfg;
+
+    // Find multiple directory paths from the initial dirs, to distribute them between threads later
+    let mut initial_dirs = initial_dirs;
+    progress.lock().unwrap().num_pending_dirs = initial_dirs.len();
+    let maybe_initial_paths = walk::walk_collect_matches_until_limit(&mut initial_dirs, FIRST_WALK_FDL, walk_ctx, is_root_walk);
+    let Ok(initial_walk) = maybe_initial_paths else {
+        return Err(std::io::Error::new(std::io::ErrorKind::Other, format!("Failed to read root path: {:?}", maybe_initial_paths.err())))
+    };
+    let initial_result = process_walk_result(walk_ctx, renderer, initial_walk);
+    let mut paths_to_distribute = Vec::new();
+    {
+        let mut tree_walk = progress.lock().unwrap();
+        tree_walk.run_stats.add_round();
+        merge_thread_result(cfg, &mut tree_walk, &mut paths_to_distribute, initial_result);
+        tree_walk.num_pending_dirs = paths_to_distribute.len();
     }
 
-    // Main thread loop
-    loop {
+    // Main thread loop, the initial walk may have already covered the whole tree. It stops early once the results are
+    // no longer wanted, enough of them have been found (`--max-results`) or it's out of time (`--timeout`)
+    while paths_to_distribute.len() > 0 && !renderer.is_sink_closed() && !walk_ctx.is_stopped() && !walk_ctx.is_timed_out() {
         // Redistribute paths
         let mut curr_num_threads = cfg.num_threads;
         if paths_to_distribute.len() < curr_num_threads {
@@ -71,74 +181,311 @@ pub fn find(target: String, root: std::path::PathBuf, cfg: &Config) -> Result<Ve
         }
         let mut paths_per_thread = distribute_paths_per_thread(&mut paths_to_distribute, curr_num_threads);
 
-        // Start "walk" on auxiliary threads
-        let new_dirs_and_results: (Vec<Vec<PathBuf>>, Vec<Vec<String>>) = paths_per_thread.par_iter_mut().map(|paths| {
-            let Ok((thread_paths_to_distribute, mut thread_categorised_results)) = walk::walk_collect_matches_until_limit(paths, cfg.file_dir_limit, cfg.label_pos, regex_target.clone(), exact_match_target) 
-            else {
-                return (vec![], vec![]);
-            };
-            
-            // All filtering is handled in auxiliary threads
-            let thread_flat_results = filter_elements(cfg, &mut thread_categorised_results).into_iter().flatten().collect();
-
-            // Not sorted -> Can print immediately and "drop" results here
-            if !cfg.is_sorted {
-                print_walk_results(&thread_flat_results);
-                return (thread_paths_to_distribute, Vec::new());
+        // Start "walk" on auxiliary threads, each one's results are merged as soon as it's finished
+        let next_paths = Mutex::new(Vec::new());
+        paths_per_thread.par_iter_mut().for_each(|paths| {
+            let num_dirs = paths.len();
+            let thread_result = walk::walk_collect_matches_until_limit(paths, cfg.file_dir_limit, walk_ctx, false).ok().map(|thread_walk| process_walk_result(walk_ctx, renderer, thread_walk));
+            let mut tree_walk = progress.lock().unwrap();
+            tree_walk.num_pending_dirs -= num_dirs;
+            if let Some(thread_result) = thread_result {
+                // The dirs it found are still to be read next round, which is never started if the walk is abandoned
+                tree_walk.num_pending_dirs += thread_result.paths_to_distribute.len();
+                merge_thread_result(cfg, &mut tree_walk, &mut next_paths.lock().unwrap(), thread_result);
             }
-            
-            return (thread_paths_to_distribute, thread_flat_results)
-        }).unzip();
-
-        // Retrieve paths to distribute and add to all_results    
-        paths_to_distribute = new_dirs_and_results.0.into_iter().flatten().collect();
-        flat_results.append(&mut new_dirs_and_results.1.into_iter().flatten().collect());
-        let finished_walk = paths_to_distribute.len() == 0;
-        if finished_walk {
+        });
+
+        paths_to_distribute = next_paths.into_inner().unwrap();
+        let mut tree_walk = progress.lock().unwrap();
+        tree_walk.run_stats.add_round();
+        tree_walk.num_pending_dirs = paths_to_distribute.len();
+    }
+    if paths_to_distribute.len() > 0 && walk_ctx.is_timed_out() {
+        progress.lock().unwrap().num_unvisited_dirs = Some(paths_to_distribute.len());
+    }
+    return Ok(());
+}
+
+// find_in_index, matches the pattern against the entries of an index instead of walking the filesystem. The index's
+// directories are matched in parallel chunks, which are then filtered and output the same way as walked matches
+pub fn find_in_index(target: String, idx: index::Index, cfg: &Config) -> Result<FindResult, Error> {
+    let root = idx.root;
+    let walk_ctx = walk::WalkCtx::new(cfg, &target, DeviceFilter::new(&root, false, &[])?)?;
+    let renderer = Renderer::new(cfg, &root, &walk_ctx)?;
+
+    let chunk_results: Vec<ThreadWalkResult> = idx.dirs.par_chunks(INDEX_CHUNK_DIRS).map(|dirs| {
+        let walk_result = match_indexed_dirs(&root, dirs, &walk_ctx);
+        return process_walk_result(&walk_ctx, &renderer, walk_result);
+    }).collect();
+
+    let mut ret = TreeWalk::new();
+    ret.run_stats.add_round();
+    for chunk_result in chunk_results {
+        merge_thread_result(cfg, &mut ret, &mut Vec::new(), chunk_result);
+    }
+    return finish_find(&root, &renderer, &walk_ctx, ret);
+}
+
+// match_indexed_dirs, the index equivalent of a walk, entries are matched by name and kept with the category they were
+// indexed with
+fn match_indexed_dirs(root: &std::path::Path, dirs: &[index::IndexedDir], walk_ctx: &walk::WalkCtx) -> walk::WalkResult {
+    let cfg = walk_ctx.cfg;
+    let mut ret = walk::WalkResult {
+        paths_to_distribute: Vec::new(),
+        matches: [const { Vec::new() }; matches::NUM_FILE_CATEGORIES],
+        match_counts: [0; matches::NUM_FILE_CATEGORIES],
+        walked_dirs: Vec::new(),
+        stats: walk::WalkStats { dirs_read: dirs.len(), ..Default::default() },
+    };
+    for dir in dirs {
+        if walk_ctx.is_stopped() {
             break;
         }
+        let dir_path = root.join(&dir.path);
+        if dir.path.as_os_str().len() == 0 && cfg.include_target_in_output {
+            let base_name = root.file_name().unwrap_or_default();
+            if walk_ctx.is_match(base_name) {
+                let idx = matches::category_index(walk::is_hidden_path(root), false, false);
+                record_indexed_match(&mut ret, walk_ctx, idx, walk::dir_match_path(root));
+            }
+        }
+
+        ret.stats.entries_examined += dir.entries.len();
+        for (name, category) in &dir.entries {
+            if !walk_ctx.is_match(name) {
+                continue;
+            }
+            let mut path = dir_path.join(name);
+            if *category % 3 == FT_DIR {
+                path = walk::dir_match_path(&path);
+            } else if !walk_ctx.is_content_match(&path) {
+                continue;
+            }
+            record_indexed_match(&mut ret, walk_ctx, *category, path);
+        }
     }
-    
+    return ret;
+}
+
+fn record_indexed_match(walk_result: &mut walk::WalkResult, walk_ctx: &walk::WalkCtx, idx: usize, path: PathBuf) {
+    walk_ctx.record_found(idx);
+    if walk_ctx.cfg.count_only {
+        walk_result.match_counts[idx] += 1;
+        return;
+    }
+    walk_result.matches[idx].push(path);
+}
+
+// merge_thread_result, adds the results of a single thread's walk to the results of the whole walk. With `--max-results`
+// only the first results (in sorted order) are kept, so the results never grow much larger than the limit
+fn merge_thread_result(cfg: &Config, tree_walk: &mut TreeWalk, paths_to_distribute: &mut Vec<PathBuf>, mut thread_result: ThreadWalkResult) {
+    paths_to_distribute.append(&mut thread_result.paths_to_distribute);
+    tree_walk.flat_results.append(&mut thread_result.sorted_results);
+    if let (Some(max_results), true) = (cfg.max_results, cfg.is_sorted) {
+        if tree_walk.flat_results.len() > max_results * 2 {
+            keep_first_results(cfg, &mut tree_walk.flat_results, max_results);
+        }
+    }
+    tree_walk.walked_dirs.append(&mut thread_result.walked_dirs);
+    for i in 0..matches::NUM_FILE_CATEGORIES {
+        tree_walk.match_counts[i] += thread_result.match_counts[i];
+    }
+    tree_walk.run_stats.add_walk(thread_result.thread_idx, &thread_result.stats);
+    tree_walk.run_stats.add_matches(thread_result.num_matches);
+}
+
+// process_walk_result, filters the matches of a walk, they're then either printed immediately (unsorted) or kept to be
+// sorted once the walk is finished. All filtering is handled in auxiliary threads
+fn process_walk_result(walk_ctx: &walk::WalkCtx, renderer: &Renderer, walk_result: walk::WalkResult) -> ThreadWalkResult {
+    let cfg = walk_ctx.cfg;
+    let mut ret = ThreadWalkResult {
+        paths_to_distribute: walk_result.paths_to_distribute,
+        sorted_results: Vec::new(),
+        walked_dirs: walk_result.walked_dirs,
+        match_counts: walk_result.match_counts,
+        num_matches: 0,
+        thread_idx: rayon::current_thread_index(),
+        stats: walk_result.stats,
+    };
+
+    // Prune reports and counts replace the regular output, so matches are neither printed nor kept
+    if cfg.prune_empty_report || cfg.count_only {
+        return ret;
+    }
+
+    let mut categorised_results = walk_result.matches;
+    let mut filtered_results = filter_elements(cfg, &mut categorised_results);
+    if !cfg.is_sorted {
+        let num_claimed = walk_ctx.claim_results(filtered_results.iter().map(|(_, entries)| entries.len()).sum());
+        truncate_categorised(&mut filtered_results, num_claimed);
+    }
+    ret.num_matches = filtered_results.iter().map(|(_, entries)| entries.len()).sum();
+
+    // Not sorted -> Can render, print immediately and "drop" results here. Commands and deletes only run once the walk is done
+    if !cfg.is_sorted && cfg.exec.is_none() && cfg.delete.is_none() {
+        renderer.output(renderer.render_categorised(filtered_results));
+        return ret;
+    }
+    ret.sorted_results = flatten_categorised(filtered_results);
+    return ret;
+}
+
+// collect_output, the output that's only known once the walk is finished, i.e. anything that isn't printed by the threads
+#[allow(clippy::too_many_arguments)]
+fn collect_output(cfg: &Config, root: &std::path::Path, renderer: &Renderer, walk_ctx: &walk::WalkCtx, mut flat_results: Vec<(PathBuf, usize)>, walked_dirs: Vec<walk::WalkedDir>, match_counts: &[usize; matches::NUM_FILE_CATEGORIES], run_stats: &mut RunStats) -> Result<Vec<Vec<u8>>, Error> {
+    if cfg.count_only {
+        let categories = filtered_categories(cfg);
+        run_stats.add_matches(categories.iter().map(|idx| match_counts[*idx]).sum());
+        return Ok(stats::count_lines(&categories, match_counts));
+    }
+
+    if cfg.prune_empty_report {
+        let mut report = prune_empty_report(cfg, root, walked_dirs, walk_ctx);
+        run_stats.add_matches(report.len());
+        report.par_sort_by(|a, b| {
+            return a.0.as_os_str().as_bytes().cmp(b.0.as_os_str().as_bytes()).reverse();
+        });
+        return Ok(report.into_par_iter().map(|(path, category)| renderer.render(&path, category)).collect());
+    }
+
+    if let Some(delete) = &cfg.delete {
+        let is_unfiltered = walk_ctx.match_any && !cfg.is_filtered && !cfg.match_empty && cfg.name_filter.is_none() && cfg.contains.is_none() && cfg.expr.is_none();
+        delete.sort_and_check(root, &mut flat_results, is_unfiltered)?;
+        if delete.dry_run {
+            return Ok(flat_results.into_par_iter().map(|(path, category)| renderer.render(&path, category)).collect());
+        }
+        let entries: Vec<(PathBuf, bool)> = flat_results.into_iter().map(|(path, category)| (path, category % 3 == FT_DIR)).collect();
+        delete.run(root, &entries);
+        return Ok(Vec::new());
+    }
+
+    if let Some(exec) = &cfg.exec {
+        if cfg.is_sorted {
+            sort_results(cfg, &mut flat_results);
+        }
+        if let Some(max_results) = cfg.max_results {
+            flat_results.truncate(max_results);
+        }
+        let paths: Vec<PathBuf> = flat_results.into_par_iter().map(|(path, _)| renderer.output_path(&path).into_owned()).collect();
+        exec.run(&paths);
+        return Ok(Vec::new());
+    }
+
+    if cfg.save_snapshot.is_some() || cfg.diff_snapshot.is_some() {
+        return snapshot_output(cfg, renderer, flat_results);
+    }
+
     // Not sorted -> Threads handle printing so nothing to return
     if !cfg.is_sorted {
-        return Ok(Vec::new())
+        return Ok(Vec::new());
+    }
+
+    if let Some(fuzzy) = &cfg.fuzzy {
+        flat_results = fuzzy_rank(cfg, fuzzy, root, flat_results);
+        run_stats.set_matches(flat_results.len());
+    } else {
+        sort_results(cfg, &mut flat_results);
+    }
+    if let Some(max_results) = cfg.max_results {
+        flat_results.truncate(max_results);
     }
+    if cfg.tree {
+        return Ok(tree::render_tree(flat_results, root, renderer, cfg.tree_depth));
+    }
+    return Ok(flat_results.into_par_iter().map(|(path, category)| renderer.render(&path, category)).collect());
+}
 
-    // Custom start/end offsets are used when comparing paths, to ensure labels AREN'T included
-    let mut start_cmp_str_offset = 0;
-    let mut end_cmp_str_offset = 0;
-    if cfg.label_pos != 0 {
-        start_cmp_str_offset = label::LABEL_LENGTH + 1;
-        if cfg.label_pos > 0 {
-            start_cmp_str_offset = 0;
-            end_cmp_str_offset = label::LABEL_LENGTH + 1;
+// snapshot_output, saves the results as a snapshot and/or outputs just the entries that differ from an earlier one, with
+// a marker. Without `--diff-snapshot` the results are output as usual
+fn snapshot_output(cfg: &Config, renderer: &Renderer, mut flat_results: Vec<(PathBuf, usize)>) -> Result<Vec<Vec<u8>>, Error> {
+    flat_results.par_sort_by(|a, b| {
+        return a.0.as_os_str().as_bytes().cmp(b.0.as_os_str().as_bytes());
+    });
+    let curr_snapshot = snapshot::Snapshot::from_results(cfg, &flat_results);
+    let mut ret: Vec<Vec<u8>> = match &cfg.diff_snapshot {
+        Some(diff_path) => {
+            let prev_snapshot = snapshot::Snapshot::read(diff_path)?;
+            curr_snapshot.diff(&prev_snapshot).into_par_iter().map(|(marker, entry)| {
+                let mut line = vec![marker, b' '];
+                line.extend_from_slice(&renderer.render(&entry.path, entry.category));
+                return line;
+            }).collect()
         }
+        None => flat_results.into_par_iter().map(|(path, category)| renderer.render(&path, category)).collect(),
+    };
+    if let Some(save_path) = &cfg.save_snapshot {
+        curr_snapshot.write(save_path)?;
+    }
+    if !cfg.sort_asc {
+        ret.reverse();
     }
+    return Ok(ret);
+}
+
+// sort_results, paths are compared by their bytes, labels and other formatting are only added once they're sorted
+fn sort_results(cfg: &Config, flat_results: &mut [(PathBuf, usize)]) {
+    flat_results.par_sort_by(|a, b| cmp_results(cfg, a, b));
+}
+
+fn cmp_results(cfg: &Config, a: &(PathBuf, usize), b: &(PathBuf, usize)) -> std::cmp::Ordering {
+    let ord = a.0.as_os_str().as_bytes().cmp(b.0.as_os_str().as_bytes());
     if cfg.sort_asc {
-        flat_results.par_sort_by(|a: &String, b: &String| {
-            return a[start_cmp_str_offset..a.len() - end_cmp_str_offset].cmp(&b[start_cmp_str_offset..b.len() - end_cmp_str_offset]);
-        });
-    } else {
-        flat_results.par_sort_by(|a: &String, b: &String| {
-            return a[start_cmp_str_offset..a.len() - end_cmp_str_offset].cmp(&b[start_cmp_str_offset..b.len() - end_cmp_str_offset]).reverse();
-        });
+        return ord;
     }
-    return Ok(flat_results);
+    return ord.reverse();
 }
 
-fn print_walk_results(results: &Vec<String>) {
-    if results.len() == 0 {
+// keep_first_results, keeps (unordered) just the first `n` results in sorted order, without sorting all of them
+fn keep_first_results(cfg: &Config, flat_results: &mut Vec<(PathBuf, usize)>, n: usize) {
+    if n == 0 {
+        flat_results.clear();
         return;
     }
+    if flat_results.len() > n {
+        flat_results.select_nth_unstable_by(n - 1, |a, b| cmp_results(cfg, a, b));
+        flat_results.truncate(n);
+    }
+}
+
+// truncate_categorised, keeps the first `n` results, in category order
+fn truncate_categorised(categorised: &mut [(usize, Vec<PathBuf>)], n: usize) {
+    let mut remaining = n;
+    for (_, entries) in categorised.iter_mut() {
+        entries.truncate(remaining);
+        remaining -= entries.len();
+    }
+}
+
+// fuzzy_rank, sorts the results by their `--fuzzy` score (best first), then the shortest path. Basenames were already
+// matched during the walk, full paths (relative to the root) are matched here
+fn fuzzy_rank(cfg: &Config, fuzzy: &fuzzy::FuzzyQuery, root: &std::path::Path, flat_results: Vec<(PathBuf, usize)>) -> Vec<(PathBuf, usize)> {
+    let mut scored: Vec<(i64, PathBuf, usize)> = flat_results.into_par_iter().filter_map(|(path, category)| {
+        let candidate = if cfg.fuzzy_full_path { path.strip_prefix(root).unwrap_or(&path) } else { std::path::Path::new(path.file_name().unwrap_or_default()) };
+        let score = fuzzy.score(candidate.as_os_str().as_bytes())?;
+        return Some((score, path, category));
+    }).collect();
+    scored.par_sort_by(|a, b| {
+        return b.0.cmp(&a.0).then_with(|| a.1.as_os_str().len().cmp(&b.1.as_os_str().len())).then_with(|| a.1.as_os_str().as_bytes().cmp(b.1.as_os_str().as_bytes()));
+    });
+    if let Some(top) = cfg.top {
+        scored.truncate(top);
+    }
+    if !cfg.sort_asc {
+        scored.reverse();
+    }
+    return scored.into_iter().map(|(_, path, category)| (path, category)).collect();
+}
 
-    let mut lines: Vec<String> = Vec::with_capacity(results.len());
-    for ff in results {
-        lines.push(ff.clone());
+// flatten_categorised, keeps the category of each entry so it can still be rendered after sorting
+fn flatten_categorised(categorised: Vec<(usize, Vec<PathBuf>)>) -> Vec<(PathBuf, usize)> {
+    let mut ret = Vec::with_capacity(categorised.iter().map(|(_, entries)| entries.len()).sum());
+    for (category, entries) in categorised {
+        ret.extend(entries.into_iter().map(|ent| (ent, category)));
     }
-    let output_str = format!("{}\n", lines.join("\n"));
-    let _ = std::io::stdout().write(output_str.as_bytes());
+    return ret;
 }
 
+#[allow(clippy::needless_range_loop)]
 fn distribute_paths_per_thread(paths_to_distribute_and_free: &mut Vec<PathBuf>, num_threads: usize) -> Vec<Vec<PathBuf>> {
     // distribute paths such that each thread gets a "fair" allocation of low and high index elements
     let max_num_paths_per_thread = (paths_to_distribute_and_free.len() / num_threads) + 1;
@@ -160,8 +507,14 @@ fn distribute_paths_per_thread(paths_to_distribute_and_free: &mut Vec<PathBuf>,
     return per_thread_paths;
 }
 
-// filter_elements, determines which indices in the Vec<Vec<FoundFile>> to retrieve based on filters in config
-fn filter_elements(cfg: &Config, original: &mut [Vec<String>; matches::NUM_FILE_CATEGORIES]) -> Vec<Vec<String>> {
+// filter_elements, determines which indices in the Vec<Vec<FoundFile>> to retrieve based on filters in config, returning
+// each of them alongside their index
+fn filter_elements(cfg: &Config, original: &mut [Vec<PathBuf>; matches::NUM_FILE_CATEGORIES]) -> Vec<(usize, Vec<PathBuf>)> {
+    return filtered_categories(cfg).into_iter().map(|idx| (idx, std::mem::take(&mut original[idx]))).collect();
+}
+
+// filtered_categories, the indices of the categories that are output, based on filters in config
+pub fn filtered_categories(cfg: &Config) -> Vec<usize> {
     let mut filtered_hidden = vec![0, 1];
     let mut filtered_types = vec![FT_FILE, FT_SYMLINK, FT_DIR];
     if cfg.is_filtered {
@@ -188,9 +541,39 @@ fn filter_elements(cfg: &Config, original: &mut [Vec<String>; matches::NUM_FILE_
     let mut ret = Vec::with_capacity(filtered_hidden.len() * filtered_types.len());
     for is_hidden in filtered_hidden {
         for ft in &filtered_types {
-            let filtered_idx = (is_hidden as usize * 3) + *ft;
-            ret.push(std::mem::take(&mut original[filtered_idx]));
+            ret.push((is_hidden * 3) + *ft);
         }
     }
     return ret;
-}
\ No newline at end of file
+}
+
+// prune_empty_report, lists the matching directories that contain nothing but (recursively) empty directories. Children
+// are listed before their parents, so the output can be passed straight to `rmdir`
+fn prune_empty_report(cfg: &Config, root: &std::path::Path, walked_dirs: Vec<walk::WalkedDir>, walk_ctx: &walk::WalkCtx) -> Vec<(PathBuf, usize)> {
+    // Any directory that (indirectly) contains a non-directory can't be pruned, neither can its ancestors
+    let mut unprunable: HashSet<PathBuf> = HashSet::new();
+    for wd in &walked_dirs {
+        if !wd.has_non_dir_entries {
+            continue;
+        }
+        let mut curr = Some(wd.path.as_path());
+        while let Some(p) = curr {
+            if !unprunable.insert(p.to_path_buf()) || p == root {
+                break;
+            }
+            curr = p.parent();
+        }
+    }
+
+    return walked_dirs.into_par_iter().filter_map(|wd| {
+        if unprunable.contains(&wd.path) || (!cfg.include_target_in_output && wd.path == root) {
+            return None;
+        }
+        let base_name = wd.path.file_name().unwrap_or(wd.path.as_os_str());
+        if !walk_ctx.is_match(base_name) {
+            return None;
+        }
+        let category = (walk::is_hidden_path(&wd.path) as usize * 3) + FT_DIR;
+        return Some((walk::dir_match_path(&wd.path), category));
+    }).collect();
+}
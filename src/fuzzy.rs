@@ -0,0 +1,106 @@
+const SCORE_MATCH: i64 = 16;
+const BONUS_BOUNDARY: i64 = 10;
+const BONUS_CAMEL_CASE: i64 = 8;
+const BONUS_CONSECUTIVE: i64 = 8;
+const PENALTY_GAP: i64 = 1;
+
+// FuzzyQuery, a `--fuzzy` query, matched as a subsequence of a candidate's bytes. It's case insensitive unless the query
+// contains an uppercase letter ("smart case")
+pub struct FuzzyQuery {
+    pub query: Vec<u8>,
+    pub is_case_sensitive: bool,
+}
+
+impl FuzzyQuery {
+    pub fn new(query: &str) -> FuzzyQuery {
+        let is_case_sensitive = query.bytes().any(|b| b.is_ascii_uppercase());
+        let query = if is_case_sensitive { query.as_bytes().to_vec() } else { query.to_ascii_lowercase().into_bytes() };
+        return FuzzyQuery { query, is_case_sensitive };
+    }
+
+    fn eq(&self, query_byte: u8, candidate_byte: u8) -> bool {
+        if self.is_case_sensitive {
+            return query_byte == candidate_byte;
+        }
+        return query_byte == candidate_byte.to_ascii_lowercase();
+    }
+
+    // is_match, whether every byte of the query appears in the candidate, in order
+    pub fn is_match(&self, candidate: &[u8]) -> bool {
+        let mut q_idx = 0;
+        for c in candidate {
+            if q_idx == self.query.len() {
+                break;
+            }
+            if self.eq(self.query[q_idx], *c) {
+                q_idx += 1;
+            }
+        }
+        return q_idx == self.query.len();
+    }
+
+    // score, higher is better. The shortest window ending at the first complete match is found by scanning back from it
+    // (the same approach as fzf's v1 algorithm), then each matched byte scores more at the start of a word and straight
+    // after the previous match, and each skipped byte in the window costs a little
+    pub fn score(&self, candidate: &[u8]) -> Option<i64> {
+        if self.query.len() == 0 {
+            return Some(0);
+        }
+
+        let mut q_idx = 0;
+        let mut end = None;
+        for (idx, c) in candidate.iter().enumerate() {
+            if self.eq(self.query[q_idx], *c) {
+                q_idx += 1;
+                if q_idx == self.query.len() {
+                    end = Some(idx);
+                    break;
+                }
+            }
+        }
+        let end = end?;
+
+        let mut start = end;
+        let mut q_idx = self.query.len();
+        for idx in (0..=end).rev() {
+            if self.eq(self.query[q_idx - 1], candidate[idx]) {
+                q_idx -= 1;
+                if q_idx == 0 {
+                    start = idx;
+                    break;
+                }
+            }
+        }
+
+        let mut ret = 0;
+        let mut q_idx = 0;
+        let mut prev_match_idx = None;
+        for idx in start..=end {
+            if q_idx == self.query.len() || !self.eq(self.query[q_idx], candidate[idx]) {
+                ret -= PENALTY_GAP;
+                continue;
+            }
+            ret += SCORE_MATCH + boundary_bonus(candidate, idx);
+            if prev_match_idx == Some(idx.wrapping_sub(1)) {
+                ret += BONUS_CONSECUTIVE;
+            }
+            prev_match_idx = Some(idx);
+            q_idx += 1;
+        }
+        return Some(ret);
+    }
+}
+
+// boundary_bonus, the start of the candidate or of a word (after a separator, or an uppercase letter after a lowercase one)
+fn boundary_bonus(candidate: &[u8], idx: usize) -> i64 {
+    if idx == 0 {
+        return BONUS_BOUNDARY;
+    }
+    let (prev, curr) = (candidate[idx - 1], candidate[idx]);
+    if matches!(prev, b'/' | b'_' | b'-' | b'.' | b' ') {
+        return BONUS_BOUNDARY;
+    } else if prev.is_ascii_lowercase() && curr.is_ascii_uppercase() {
+        return BONUS_CAMEL_CASE;
+    }
+    return 0;
+}
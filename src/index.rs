@@ -0,0 +1,276 @@
+use rayon::iter::{IntoParallelIterator, ParallelIterator};
+use std::collections::HashMap;
+use std::ffi::OsString;
+use std::io::Write;
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use std::path::{Path, PathBuf};
+
+use crate::find;
+use crate::mounts::DeviceFilter;
+use crate::output::Renderer;
+use crate::walk::{self, DirTimes};
+use crate::Config;
+
+const INDEX_MAGIC: &[u8; 8] = b"PFFINDEX";
+const INDEX_VERSION: u32 = 2;
+// The smallest a dir (path length, times and number of entries) and an entry (category and name length) can be on disk
+const MIN_DIR_LEN: usize = 4 + 8 + 8 + 4;
+const MIN_ENTRY_LEN: usize = 1 + 2;
+
+// IndexedDir, a directory's path (relative to the index root, the root itself is empty), its times when it was read and
+// the name and category of each of its entries
+#[derive(Clone)]
+pub struct IndexedDir {
+    pub path: PathBuf,
+    pub times: DirTimes,
+    pub entries: Vec<(OsString, usize)>,
+}
+
+// Index, a snapshot of every entry under `root`, stored on disk as (all integers are little endian):
+//   magic (8 bytes) | version (u32) | root length (u32) | root | number of dirs (u64)
+//   then for each dir: path length (u32) | path | mtime (i64 ns) | ctime (i64 ns) | number of entries (u32)
+//   then for each entry: category (u8) | name length (u16) | name
+pub struct Index {
+    pub root: PathBuf,
+    pub dirs: Vec<IndexedDir>,
+}
+
+impl Index {
+    pub fn read(path: &Path) -> std::io::Result<Index> {
+        let bytes = std::fs::read(path)?;
+        let mut reader = IndexReader { bytes: &bytes, pos: 0 };
+        if reader.take(INDEX_MAGIC.len())? != INDEX_MAGIC {
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, format!("'{}' isn't a `pff` index", path.display())));
+        }
+        let version = reader.read_u32()?;
+        if version != INDEX_VERSION {
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, format!("unsupported index version {}, expected {}, rebuild it with `pff index build`", version, INDEX_VERSION)));
+        }
+
+        let root_len = reader.read_u32()? as usize;
+        let root = PathBuf::from(OsString::from_vec(reader.take(root_len)?.to_vec()));
+        let num_dirs = reader.read_u64()? as usize;
+        let mut dirs = Vec::with_capacity(reader.capacity_for(num_dirs, MIN_DIR_LEN));
+        for _ in 0..num_dirs {
+            let path_len = reader.read_u32()? as usize;
+            let dir_path = PathBuf::from(OsString::from_vec(reader.take(path_len)?.to_vec()));
+            let times = DirTimes { mtime_ns: reader.read_u64()? as i64, ctime_ns: reader.read_u64()? as i64 };
+            let num_entries = reader.read_u32()? as usize;
+            let mut entries = Vec::with_capacity(reader.capacity_for(num_entries, MIN_ENTRY_LEN));
+            for _ in 0..num_entries {
+                let category = reader.take(1)?[0] as usize;
+                let name_len = u16::from_le_bytes(reader.take(2)?.try_into().unwrap()) as usize;
+                entries.push((OsString::from_vec(reader.take(name_len)?.to_vec()), category));
+            }
+            dirs.push(IndexedDir { path: dir_path, times, entries });
+        }
+        return Ok(Index { root, dirs });
+    }
+
+    // write, the index is written to a temporary file first, so a failed write never replaces an existing index
+    pub fn write(&self, path: &Path) -> std::io::Result<()> {
+        let mut tmp_path = path.as_os_str().to_os_string();
+        tmp_path.push(".tmp");
+        let mut w = std::io::BufWriter::new(std::fs::File::create(&tmp_path)?);
+
+        w.write_all(INDEX_MAGIC)?;
+        w.write_all(&INDEX_VERSION.to_le_bytes())?;
+        let root_bytes = self.root.as_os_str().as_bytes();
+        w.write_all(&(root_bytes.len() as u32).to_le_bytes())?;
+        w.write_all(root_bytes)?;
+        w.write_all(&(self.dirs.len() as u64).to_le_bytes())?;
+        for dir in &self.dirs {
+            let path_bytes = dir.path.as_os_str().as_bytes();
+            w.write_all(&(path_bytes.len() as u32).to_le_bytes())?;
+            w.write_all(path_bytes)?;
+            w.write_all(&dir.times.mtime_ns.to_le_bytes())?;
+            w.write_all(&dir.times.ctime_ns.to_le_bytes())?;
+            w.write_all(&(dir.entries.len() as u32).to_le_bytes())?;
+            for (name, category) in &dir.entries {
+                w.write_all(&[*category as u8])?;
+                w.write_all(&(name.len() as u16).to_le_bytes())?;
+                w.write_all(name.as_bytes())?;
+            }
+        }
+        w.into_inner()?.sync_all()?;
+        return std::fs::rename(&tmp_path, path);
+    }
+
+    pub fn num_entries(&self) -> usize {
+        return self.dirs.iter().map(|d| d.entries.len()).sum();
+    }
+}
+
+struct IndexReader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> IndexReader<'a> {
+    fn take(&mut self, len: usize) -> std::io::Result<&'a [u8]> {
+        if self.pos + len > self.bytes.len() {
+            return Err(std::io::Error::new(std::io::ErrorKind::UnexpectedEof, "the index is truncated"));
+        }
+        let ret = &self.bytes[self.pos..self.pos + len];
+        self.pos += len;
+        return Ok(ret);
+    }
+
+    // capacity_for, the counts are read from the file, so a corrupt one can't be trusted to size an allocation. It's
+    // clamped to how many items could fit in the bytes left, anything over that fails as truncated while reading
+    fn capacity_for(&self, count: usize, min_item_len: usize) -> usize {
+        return count.min((self.bytes.len() - self.pos) / min_item_len);
+    }
+
+    fn read_u32(&mut self) -> std::io::Result<u32> {
+        return Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()));
+    }
+
+    fn read_u64(&mut self) -> std::io::Result<u64> {
+        return Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()));
+    }
+}
+
+// run_index_command, `pff index build ROOT -o DB` or `pff index update DB`
+pub fn run_index_command(args: &[String], cfg: &mut Config) -> std::io::Result<()> {
+    let usage = "expected: `pff index build [ROOT] -o [DB]` or `pff index update [DB]`";
+    let Some(sub_command) = args.first() else {
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, format!("missing `index` command, {}", usage)));
+    };
+
+    let mut positional: Vec<&String> = Vec::new();
+    let mut db: Option<&String> = None;
+    let mut i = 1;
+    while i < args.len() {
+        if args[i] == "-o" && i + 1 < args.len() {
+            db = Some(&args[i + 1]);
+            i += 2;
+            continue;
+        }
+        positional.push(&args[i]);
+        i += 1;
+    }
+    // The index to update can be given with or without `-o`
+    if sub_command == "update" && db.is_none() {
+        db = positional.pop();
+    }
+
+    match (sub_command.as_str(), positional.as_slice(), db) {
+        ("build", [root], Some(db)) => {
+            // Indexed paths are canonical, so the index can be searched from any directory
+            let idx = build(&std::fs::canonicalize(root)?, cfg)?;
+            idx.write(Path::new(db))?;
+            println!("indexed {} entries in {} directories", idx.num_entries(), idx.dirs.len());
+        }
+        ("update", [], Some(db)) => {
+            let (idx, num_rescanned) = update(Index::read(Path::new(db))?, cfg)?;
+            idx.write(Path::new(db))?;
+            println!("rescanned {} of {} directories, indexed {} entries", num_rescanned, idx.dirs.len(), idx.num_entries());
+        }
+        _ => {
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, format!("invalid `index` arguments, {}", usage)));
+        }
+    }
+    return Ok(());
+}
+
+// build, walks the whole tree with the regular (parallel) walk, only the directories it read are kept
+fn build(root: &Path, cfg: &mut Config) -> std::io::Result<Index> {
+    let dirs = walk_dirs(root, vec![root.to_path_buf()], true, cfg)?;
+    let mut idx = Index { root: root.to_path_buf(), dirs };
+    idx.dirs.sort_by(|a, b| a.path.as_os_str().as_bytes().cmp(b.path.as_os_str().as_bytes()));
+    return Ok(idx);
+}
+
+// update, revisits the indexed directories level by level (in parallel). Unchanged directories keep their entries, changed
+// ones are read again and any new subdirectories are walked with the regular (parallel) walk. Directories that no longer
+// exist are never reached, so they're dropped. Returns the updated index and the number of directories that were read
+fn update(idx: Index, cfg: &mut Config) -> std::io::Result<(Index, usize)> {
+    let root = idx.root;
+    let old_dirs: HashMap<&Path, &IndexedDir> = idx.dirs.iter().map(|d| (d.path.as_path(), d)).collect();
+
+    let mut dirs: Vec<IndexedDir> = Vec::with_capacity(idx.dirs.len());
+    let mut new_subtrees: Vec<PathBuf> = Vec::new();
+    let mut num_rescanned = 0;
+    let mut level = vec![PathBuf::new()];
+    while level.len() > 0 {
+        let checked: Vec<(Option<IndexedDir>, bool, Vec<PathBuf>)> = level.into_par_iter().map(|rel_path| {
+            return check_dir(&root, rel_path, &old_dirs);
+        }).collect();
+
+        level = Vec::new();
+        for (maybe_dir, was_rescanned, subdirs) in checked {
+            let Some(dir) = maybe_dir else { continue };
+            num_rescanned += was_rescanned as usize;
+            for subdir in subdirs {
+                if old_dirs.contains_key(subdir.as_path()) {
+                    level.push(subdir);
+                } else {
+                    new_subtrees.push(root.join(subdir));
+                }
+            }
+            dirs.push(dir);
+        }
+    }
+
+    if new_subtrees.len() > 0 {
+        let mut new_dirs = walk_dirs(&root, new_subtrees, false, cfg)?;
+        num_rescanned += new_dirs.len();
+        dirs.append(&mut new_dirs);
+    }
+    dirs.sort_by(|a, b| a.path.as_os_str().as_bytes().cmp(b.path.as_os_str().as_bytes()));
+    return Ok((Index { root, dirs }, num_rescanned));
+}
+
+// check_dir, returns the (possibly re-read) indexed directory, whether it was re-read and its subdirectories. A
+// directory that can't be read anymore is dropped
+fn check_dir(root: &Path, rel_path: PathBuf, old_dirs: &HashMap<&Path, &IndexedDir>) -> (Option<IndexedDir>, bool, Vec<PathBuf>) {
+    let dir_path = root.join(&rel_path);
+    let Ok(md) = std::fs::symlink_metadata(&dir_path) else {
+        return (None, false, Vec::new());
+    };
+    let times = DirTimes::from_metadata(&md);
+
+    let mut ret = IndexedDir { path: rel_path, times, entries: Vec::new() };
+    let mut was_rescanned = false;
+    match old_dirs.get(ret.path.as_path()) {
+        Some(old_dir) if md.is_dir() && old_dir.times == times => {
+            ret.entries = old_dir.entries.clone();
+        }
+        _ => {
+            let Ok(dir_entries) = std::fs::read_dir(&dir_path) else {
+                return (None, false, Vec::new());
+            };
+            let dir_hidden = walk::is_hidden_path(&dir_path);
+            for ent in dir_entries {
+                let Ok(val) = ent else { continue };
+                let Ok(ft) = val.file_type() else { continue };
+                ret.entries.push((val.file_name(), walk::entry_category(dir_hidden, &val.file_name(), &ft)));
+            }
+            ret.entries.sort_by(|a, b| a.0.as_bytes().cmp(b.0.as_bytes()));
+            was_rescanned = true;
+        }
+    }
+
+    let subdirs = ret.entries.iter().filter(|(_, category)| *category % 3 == 2).map(|(name, _)| ret.path.join(name)).collect();
+    return (Some(ret), was_rescanned, subdirs);
+}
+
+// walk_dirs, walks everything under `initial_dirs` with the regular (parallel) walk, keeping just the directories it read
+fn walk_dirs(root: &Path, initial_dirs: Vec<PathBuf>, is_root_walk: bool, cfg: &mut Config) -> std::io::Result<Vec<IndexedDir>> {
+    // Matches are only counted, the index is made from the walked directories
+    cfg.index_build = true;
+    cfg.count_only = true;
+    let cfg: &Config = cfg;
+
+    let walk_ctx = walk::WalkCtx::new(cfg, "", DeviceFilter::new(root, false, &[])?)?;
+    let renderer = Renderer::new(cfg, root, &walk_ctx)?;
+    let tree_walk = find::walk_tree(initial_dirs, is_root_walk, &walk_ctx, &renderer)?;
+
+    return Ok(tree_walk.walked_dirs.into_iter().filter_map(|wd| {
+        let rel_path = wd.path.strip_prefix(root).ok()?.to_path_buf();
+        let mut entries = wd.entries;
+        entries.sort_by(|a, b| a.0.as_bytes().cmp(b.0.as_bytes()));
+        return Some(IndexedDir { path: rel_path, times: wd.times, entries });
+    }).collect());
+}
@@ -1,12 +1,24 @@
 const LABEL_DEFAULT: &str = "FRR";
-pub const LABEL_LENGTH: usize = LABEL_DEFAULT.len();
 
-pub fn add_label(s_path: &String, label_pos: i8, is_hidden: bool, is_file: bool, is_symlink: bool) -> String {
+// add_label, adds the label before or after the (raw) path, separated by `sep`
+pub fn add_label(path: &[u8], label_pos: i8, sep: u8, is_hidden: bool, is_file: bool, is_symlink: bool) -> Vec<u8> {
     let label = generate_label(is_hidden, is_file, is_symlink);
+    return join_label(path, label.as_bytes(), label_pos, sep);
+}
+
+// join_label, same as `add_label` but with an already generated (e.g. colored) label
+pub fn join_label(path: &[u8], label: &[u8], label_pos: i8, sep: u8) -> Vec<u8> {
+    let mut ret = Vec::with_capacity(path.len() + label.len() + 1);
     if label_pos == 1 {
-        return format!("{} {}", s_path, label);
+        ret.extend_from_slice(path);
+        ret.push(sep);
+        ret.extend_from_slice(label);
+        return ret;
     }
-    return format!("{} {}", label, s_path);
+    ret.extend_from_slice(label);
+    ret.push(sep);
+    ret.extend_from_slice(path);
+    return ret;
 }
 
 pub fn generate_label(is_hidden: bool, is_file: bool, is_symlink: bool) -> String {
@@ -22,12 +34,3 @@ pub fn generate_label(is_hidden: bool, is_file: bool, is_symlink: bool) -> Strin
     }
     return ret;
 }
-
-pub fn remove_label_from_string(s: String, label_pos: i8) -> String {
-    if label_pos == 0 || s.len() < (LABEL_LENGTH + 1) {
-        return s;
-    } else if label_pos > 0 {
-        return s[..s.len() - (LABEL_LENGTH + 1)].to_string();
-    }
-    return s[(LABEL_LENGTH + 1)..].to_string();
-}
\ No newline at end of file
@@ -1,15 +1,37 @@
+#![allow(clippy::needless_return, clippy::len_zero, clippy::io_other_error)]
+
 use std::env;
 use std::io::Write;
 use std::num::ParseIntError;
 use std::path::PathBuf;
 
+mod color;
+mod contents;
+mod delete;
+mod exec;
+mod expr;
+mod filetype;
 mod find;
+mod fuzzy;
+mod index;
 mod walk;
 mod matches;
 mod label;
+mod mounts;
+mod output;
+mod pick;
+mod snapshot;
+mod stats;
+mod template;
+mod tree;
+mod watch;
 
 const DEFAULT_NUM_THREADS: usize = 84;
 const DEFAULT_FD_LIMIT: usize = 2048;
+// Exit statuses, like `grep`. Finding something is a success (0)
+const EXIT_NO_MATCHES: i32 = 1;
+const EXIT_ERROR: i32 = 2;
+const EXIT_TIMEOUT: i32 = 3;
 
 struct Config {
     num_threads: usize,
@@ -26,6 +48,41 @@ struct Config {
     sort_asc: bool,
     label_pos: i8, // -1 -> start, 0 -> none, 1 -> end
     equality_match: bool,
+    match_empty: bool,
+    prune_empty_report: bool,
+    one_file_system: bool,
+    exclude_fs_types: Vec<String>,
+    format: output::Format,
+    metadata_fields: Vec<output::MetadataField>,
+    print0: bool,
+    printf: Option<template::Template>,
+    path_mode: output::PathMode,
+    color: color::ColorWhen,
+    tree: bool,
+    tree_depth: Option<usize>,
+    count_only: bool,
+    print_stats: bool,
+    exec: Option<exec::ExecCmd>,
+    delete: Option<delete::DeleteCmd>,
+    db: Option<PathBuf>,
+    index_build: bool,
+    watch: bool,
+    save_snapshot: Option<PathBuf>,
+    diff_snapshot: Option<PathBuf>,
+    expr: Option<expr::Expr>,
+    patterns: Vec<String>,
+    pattern_tag: Option<output::PatternTag>,
+    invert: bool,
+    name_filter: Option<filetype::NameFilter>,
+    contains: Option<contents::ContentMatcher>,
+    contains_count: bool,
+    fuzzy: Option<fuzzy::FuzzyQuery>,
+    fuzzy_full_path: bool,
+    top: Option<usize>,
+    pick: bool,
+    max_results: Option<usize>,
+    quiet: bool,
+    timeout: Option<std::time::Duration>,
 }
 
 fn main() {
@@ -44,42 +101,150 @@ fn main() {
         sort_asc:                 true,
         label_pos:                0,
         equality_match:           false,
+        match_empty:              false,
+        prune_empty_report:       false,
+        one_file_system:          false,
+        exclude_fs_types:         Vec::new(),
+        format:                   output::Format::Text,
+        metadata_fields:          Vec::new(),
+        print0:                   false,
+        printf:                   None,
+        path_mode:                output::PathMode::AsProvided,
+        color:                    color::ColorWhen::Auto,
+        tree:                     false,
+        tree_depth:               None,
+        count_only:               false,
+        print_stats:              false,
+        exec:                     None,
+        delete:                   None,
+        db:                       None,
+        index_build:              false,
+        watch:                    false,
+        save_snapshot:            None,
+        diff_snapshot:            None,
+        expr:                     None,
+        patterns:                 Vec::new(),
+        pattern_tag:              None,
+        invert:                   false,
+        name_filter:              None,
+        contains:                 None,
+        contains_count:           false,
+        fuzzy:                    None,
+        fuzzy_full_path:          false,
+        top:                      None,
+        pick:                     false,
+        max_results:              None,
+        quiet:                    false,
+        timeout:                  None,
     };
 
+    let args: Vec<String> = env::args().skip(1).collect();
+    if args.first().is_some_and(|a| a == "index") {
+        if let Err(e) = index::run_index_command(&args[1..], &mut cfg) {
+            eprintln!("error: {}", e);
+            std::process::exit(EXIT_ERROR);
+        }
+        return;
+    }
+
     let (target, root);
-    match eval_args(&env::args().skip(1).collect(), &mut cfg) {
+    match eval_args(&args, &mut cfg) {
         Ok(required_args) => {
             target = required_args.0;
             root = required_args.1;
-            if target.len() == 0 {
+            // `--help` and `--version` are the only valid single arguments
+            if args.len() == 1 {
                 return;
             }
         }
         Err(e ) => {
             eprintln!("error: {}", e);
-            return;
+            std::process::exit(EXIT_ERROR);
         }
     }
 
-    match find::find(target, root, &cfg) {
-        Ok(entries) => {
-            if entries.len() == 0 {
-                return;
+    if cfg.watch {
+        if let Err(e) = watch::watch(target, root, &cfg) {
+            eprintln!("error: {}", e);
+            std::process::exit(EXIT_ERROR);
+        }
+        return;
+    }
+
+    // With `--timeout` a stuck walk is abandoned, rather than waited for, so the config has to outlive it
+    let cfg: &'static Config = Box::leak(Box::new(cfg));
+    let find_result = match (&cfg.db, cfg.timeout) {
+        (None, _) if cfg.pick => pick::pick(target, root, cfg),
+        (Some(db), _) => index::Index::read(db).and_then(|idx| find::find_in_index(target, idx, cfg)),
+        (None, Some(timeout)) => find::find_with_watchdog(target, root, cfg, timeout),
+        (None, None) => find::find(target, root, cfg),
+    };
+    match find_result {
+        Ok(result) => {
+            // Any failed `--exec` command is reflected in the exit status
+            if let Some(exec) = &cfg.exec {
+                let num_failures = exec.num_failures();
+                if num_failures > 0 {
+                    eprintln!("error: {} command(s) failed", num_failures);
+                    std::process::exit(EXIT_ERROR);
+                }
+            }
+            if let Some(delete) = &cfg.delete {
+                let num_failures = delete.num_failures();
+                if num_failures > 0 {
+                    eprintln!("error: failed to delete {} entry(s)", num_failures);
+                    std::process::exit(EXIT_ERROR);
+                }
+            }
+
+            // Results run through `--exec` or `--delete` aren't output, so there's no (empty) json array to print either
+            let is_output_consumed = cfg.exec.is_some() || cfg.delete.as_ref().is_some_and(|d| !d.dry_run);
+            if !cfg.quiet && !is_output_consumed && (result.output.len() > 0 || cfg.format == output::Format::Json) {
+                let res = std::io::stdout().lock().write_all(&output::join_results(cfg, &result.output));
+                if res.is_err() {
+                    eprintln!("failed to write `find` results to stdout: {:?}", res.err());
+                    std::process::exit(EXIT_ERROR);
+                }
+            }
+            // A timeout takes precedence over whether anything matched, as the results are incomplete
+            if let (Some(timeout), Some(num_unvisited_dirs)) = (cfg.timeout, result.num_unvisited_dirs) {
+                eprintln!("error: timed out after {:?}, {} directories weren't searched", timeout, num_unvisited_dirs);
+                std::process::exit(EXIT_TIMEOUT);
+            }
+            // Entries that couldn't be read (e.g. permission denied) make the results incomplete too, but with `--quiet` a
+            // match is enough (the same as `grep -q`)
+            if result.num_errors > 0 && !(cfg.quiet && result.num_matches > 0) {
+                eprintln!("error: {} directory(s) or entry(s) couldn't be read", result.num_errors);
+                std::process::exit(EXIT_ERROR);
             }
-            
-            let res = std::io::stdout().write(format!("{}\n", entries.join("\n")).as_bytes());
-            if res.is_err() {
-                eprintln!("failed to write `find` results to stdout: {:?}", res.err());
+            if result.num_matches == 0 {
+                std::process::exit(EXIT_NO_MATCHES);
             }
         }
         Err(e) => {
             eprintln!("error: {}", e);
+            std::process::exit(EXIT_ERROR);
         }
     }
 }
 
+// option_arg_idxs, the indexes of the arguments that could be options, the command of an `--exec` or `--exec-batch` (up
+// to its ';') is skipped so its arguments (e.g. `grep -e`) aren't mistaken for ours
+fn option_arg_idxs(args: &[String]) -> Vec<usize> {
+    let mut ret = Vec::with_capacity(args.len());
+    let mut is_exec_cmd = false;
+    for (idx, arg) in args.iter().enumerate() {
+        if is_exec_cmd {
+            is_exec_cmd = arg != ";";
+            continue;
+        }
+        is_exec_cmd = arg == "--exec" || arg == "--exec-batch";
+        ret.push(idx);
+    }
+    return ret;
+}
 
-fn eval_args(args: &Vec<String>, config: &mut Config) -> std::io::Result<(String, PathBuf)> {
+fn eval_args(args: &[String], config: &mut Config) -> std::io::Result<(String, PathBuf)> {
     // Length Checks / Help Output
     let default_ret = (String::new(), PathBuf::new());
     if args.len() == 0 {
@@ -100,22 +265,67 @@ fn eval_args(args: &Vec<String>, config: &mut Config) -> std::io::Result<(String
         return Ok(default_ret);
     }
 
-    // Required Args
-    let target = args[args.len() - 2].to_string();
-    let root = &args[args.len() - 1];
-    if !std::fs::exists(root)? {
-        return Err(std::io::Error::new(std::io::ErrorKind::NotFound, format!("provided path '{}', does not exist", root)));
+    // Required Args, searching an index (`--db`) only takes a pattern, the root is the index's. An expression takes the
+    // place of both, after the root (`--find ROOT EXPRESSION...`, the same order as `find`)
+    let option_idxs = option_arg_idxs(args);
+    let find_idx = option_idxs.iter().copied().find(|idx| args[*idx] == "--find");
+    let db_idx = option_idxs.iter().copied().find(|idx| *idx < args.len() - 1 && args[*idx] == "--db");
+    let uses_patterns = option_idxs.iter().any(|idx| *idx < args.len() - 1 && matches!(args[*idx].as_str(), "-e" | "--patterns-from" | "--fuzzy"));
+    let mut target = String::new();
+    let mut root = None;
+    let first_non_optional_arg_idx;
+    if let Some(find_idx) = find_idx {
+        let Some(find_root) = args.get(find_idx + 1) else {
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "missing root directory for `--find`"));
+        };
+        match expr::Expr::compile(&args[find_idx + 2..]) {
+            Ok(e) => { config.expr = Some(e); }
+            Err(e) => {
+                return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, e));
+            }
+        }
+        // Same as `find`, the root is evaluated too
+        config.include_target_in_output = true;
+        root = Some(find_root);
+        first_non_optional_arg_idx = find_idx;
+    } else if let Some(db_idx) = db_idx {
+        // Patterns given with `-e` (or a `--fuzzy` query) replace the positional pattern
+        first_non_optional_arg_idx = args.len() - (!uses_patterns as usize);
+        if db_idx + 1 >= first_non_optional_arg_idx {
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "missing pattern for `--db`, expected: `pff --db INDEX [PATTERN]`"));
+        }
+        if !uses_patterns {
+            target = args[args.len() - 1].to_string();
+        }
+    } else {
+        root = Some(&args[args.len() - 1]);
+        first_non_optional_arg_idx = args.len() - 1 - (!uses_patterns as usize);
+        if !uses_patterns {
+            target = args[args.len() - 2].to_string();
+        }
     }
-    let root_pb = PathBuf::from(root);
-    let has_optional_args = args.len() > 2;
+    let mut root_pb = PathBuf::new();
+    if let Some(root) = root {
+        if !std::fs::exists(root)? {
+            return Err(std::io::Error::new(std::io::ErrorKind::NotFound, format!("provided path '{}', does not exist", root)));
+        }
+        root_pb = PathBuf::from(root);
+    }
+    let has_optional_args = first_non_optional_arg_idx > 0;
     if !has_optional_args {
         return Ok((target, root_pb));
     }
 
     // Optional Args
     let mut i = 0;
-    let first_non_optional_arg_idx = args.len() - 2;
-    let valid_command_options = vec!["--include-target", "-eq", "--filter", "--sort", "--label", "-t", "-fdl"];
+    let mut delete_dry_run = false;
+    let mut delete_force = false;
+    let mut extensions: Vec<String> = Vec::new();
+    let mut type_names: Vec<String> = Vec::new();
+    let mut type_adds: Vec<(String, Vec<String>)> = Vec::new();
+    let mut max_filesize = None;
+    let mut search_binary = false;
+    let valid_command_options = vec!["--include-target", "-eq", "--filter", "--sort", "--label", "-t", "-fdl", "--empty", "--prune-empty-report", "--one-file-system", "-xdev", "--exclude-fs-type", "--format", "--metadata", "-0", "--print0", "--printf", "--relative", "--absolute", "--canonical", "--color", "--tree", "--count", "--stats", "--exec", "--exec-batch", "--delete", "--dry-run", "--force", "--db", "--watch", "--save-snapshot", "--diff-snapshot", "-e", "--patterns-from", "--pattern-tag", "-v", "--invert", "-x", "--extension", "--type", "--type-add", "--contains", "--contains-literal", "--contains-count", "--max-filesize", "--binary", "--fuzzy", "--fuzzy-path", "--top", "--pick", "--max-results", "-1", "--quit", "-q", "--quiet", "--timeout"];
     while i < first_non_optional_arg_idx {
         let curr = args[i].as_str();
         if !valid_command_options.contains(&curr) {
@@ -131,6 +341,66 @@ fn eval_args(args: &Vec<String>, config: &mut Config) -> std::io::Result<(String
             "-eq" => {
                 config.equality_match = true;
             }
+            "--empty" => {
+                config.match_empty = true;
+            }
+            "--prune-empty-report" => {
+                config.prune_empty_report = true;
+            }
+            "--relative" => {
+                config.path_mode = output::PathMode::Relative;
+            }
+            "--absolute" => {
+                config.path_mode = output::PathMode::Absolute;
+            }
+            "--canonical" => {
+                config.path_mode = output::PathMode::Canonical;
+            }
+            "--count" => {
+                config.count_only = true;
+            }
+            "--stats" => {
+                config.print_stats = true;
+            }
+            "--contains-count" => {
+                config.contains_count = true;
+            }
+            "--binary" => {
+                search_binary = true;
+            }
+            "-q" | "--quiet" => {
+                config.quiet = true;
+            }
+            "-1" | "--quit" => {
+                config.max_results = Some(1);
+            }
+            "--pick" => {
+                config.pick = true;
+            }
+            "--fuzzy-path" => {
+                config.fuzzy_full_path = true;
+            }
+            "-v" | "--invert" => {
+                config.invert = true;
+            }
+            "--watch" => {
+                config.watch = true;
+            }
+            "--delete" => {
+                config.delete = Some(delete::DeleteCmd::new(false, false));
+            }
+            "--dry-run" => {
+                delete_dry_run = true;
+            }
+            "--force" => {
+                delete_force = true;
+            }
+            "-0" | "--print0" => {
+                config.print0 = true;
+            }
+            "--one-file-system" | "-xdev" => {
+                config.one_file_system = true;
+            }
             _ => { is_valid_opt = false; }
         }
         i += 1;
@@ -139,7 +409,7 @@ fn eval_args(args: &Vec<String>, config: &mut Config) -> std::io::Result<(String
         }
         
         // Multi-value args
-        if i + 1 >= args.len() {
+        if i > first_non_optional_arg_idx {
             return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, format!("missing additional argument for '{}' flag", curr)));
         }
         let mut next = args[i].as_str();
@@ -221,6 +491,21 @@ fn eval_args(args: &Vec<String>, config: &mut Config) -> std::io::Result<(String
                 config.is_sorted = true;
                 config.sort_asc = is_asc;
             }
+            "--tree" => {
+                // The tree is built from the sorted results, an optional depth collapses anything deeper into a count. The
+                // depth has to come before the pattern, so a numeric pattern (e.g. `--tree 1 <dir>`) isn't taken as one
+                let maybe_depth = if i < first_non_optional_arg_idx { next.parse::<usize>().ok() } else { None };
+                if let Some(depth) = maybe_depth {
+                    if depth < 1 {
+                        return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "invalid `--tree` depth argument, must be a positive integer"));
+                    }
+                    config.tree_depth = Some(depth);
+                } else {
+                    i -= 1;
+                }
+                config.tree = true;
+                config.is_sorted = true;
+            }
             "--label" => {
                 let mut pos = -1;
                 if next == "end" {
@@ -237,28 +522,332 @@ fn eval_args(args: &Vec<String>, config: &mut Config) -> std::io::Result<(String
                 }
                 config.file_dir_limit = maybe_file_dir_limit.unwrap();
             }
+            "--exclude-fs-type" => {
+                let fs_types: Vec<String> = next.split(',').filter(|t| t.len() > 0).map(|t| t.to_string()).collect();
+                if fs_types.len() == 0 {
+                    return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "invalid `--exclude-fs-type` argument, must be a comma separated list of filesystem types"));
+                }
+                config.exclude_fs_types.extend(fs_types);
+            }
+            "--color" => {
+                match next {
+                    "auto" => { config.color = color::ColorWhen::Auto; }
+                    "always" => { config.color = color::ColorWhen::Always; }
+                    "never" => { config.color = color::ColorWhen::Never; }
+                    _ => {
+                        return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, format!("invalid option: '{}', provided for --color, must be one of: auto, always, never", next)));
+                    }
+                }
+            }
+            "--exec" | "--exec-batch" => {
+                // The command is every argument up to a ';' (same as `find -exec`)
+                let Some(cmd_len) = args[i..first_non_optional_arg_idx].iter().position(|a| a == ";") else {
+                    return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, format!("missing ';' at the end of the '{}' command", curr)));
+                };
+                match exec::ExecCmd::new(&args[i..i + cmd_len], curr == "--exec-batch") {
+                    Ok(cmd) => { config.exec = Some(cmd); }
+                    Err(e) => {
+                        return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, e));
+                    }
+                }
+                i += cmd_len;
+            }
+            "--format" => {
+                match next {
+                    "text" => { config.format = output::Format::Text; }
+                    "ndjson" => { config.format = output::Format::Ndjson; }
+                    "json" => {
+                        // A JSON array can only be written once all results are known, so they're always collected and sorted
+                        config.format = output::Format::Json;
+                        config.is_sorted = true;
+                    }
+                    _ => {
+                        return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, format!("invalid option: '{}', provided for --format, must be one of: text, json, ndjson", next)));
+                    }
+                }
+            }
+            "--db" => {
+                config.db = Some(PathBuf::from(next));
+            }
+            "-e" => {
+                config.patterns.push(next.to_string());
+            }
+            "--patterns-from" => {
+                // One pattern per line, empty lines are skipped
+                let contents = std::fs::read_to_string(next)?;
+                config.patterns.extend(contents.lines().filter(|l| l.len() > 0).map(String::from));
+            }
+            "-x" | "--extension" | "--type" => {
+                let values: Vec<String> = next.split(',').filter(|v| v.len() > 0).map(String::from).collect();
+                if values.len() == 0 {
+                    return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, format!("invalid `{}` argument, must be a comma separated list", curr)));
+                }
+                if curr == "--type" {
+                    type_names.extend(values);
+                } else {
+                    extensions.extend(values);
+                }
+            }
+            "--contains" | "--contains-literal" => {
+                match contents::ContentMatcher::new(next, curr == "--contains-literal") {
+                    Ok(contains) => { config.contains = Some(contains); }
+                    Err(e) => {
+                        return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, e));
+                    }
+                }
+            }
+            "--max-filesize" => {
+                let Some(size) = contents::parse_size(next) else {
+                    return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "invalid `--max-filesize` argument, must be a number of bytes with an optional K, M or G suffix"));
+                };
+                max_filesize = Some(size);
+            }
+            "--fuzzy" => {
+                // Results are ranked by score, so they're always collected and sorted
+                config.fuzzy = Some(fuzzy::FuzzyQuery::new(next));
+                config.is_sorted = true;
+            }
+            "--timeout" => {
+                let Some(timeout) = walk::parse_duration(next) else {
+                    return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "invalid `--timeout` argument, must be a number with an optional unit: ms, s (default), m or h, e.g. '5s'"));
+                };
+                config.timeout = Some(timeout);
+            }
+            "--max-results" => {
+                let Ok(max_results) = next.parse::<usize>() else {
+                    return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "invalid `--max-results` argument, must be a non-negative integer"));
+                };
+                config.max_results = Some(max_results);
+            }
+            "--top" => {
+                let Ok(top) = next.parse::<usize>() else {
+                    return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "invalid `--top` argument, must be a non-negative integer"));
+                };
+                config.top = Some(top);
+            }
+            "--type-add" => {
+                match filetype::parse_type_add(next) {
+                    Ok(type_add) => { type_adds.push(type_add); }
+                    Err(e) => {
+                        return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, e));
+                    }
+                }
+            }
+            "--pattern-tag" => {
+                match next {
+                    "index" => { config.pattern_tag = Some(output::PatternTag::Index); }
+                    "pattern" => { config.pattern_tag = Some(output::PatternTag::Pattern); }
+                    _ => {
+                        return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, format!("invalid option: '{}', provided for --pattern-tag, must be one of: index, pattern", next)));
+                    }
+                }
+            }
+            "--save-snapshot" | "--diff-snapshot" => {
+                // Snapshots are compared by their sorted paths, so results are always collected and sorted
+                config.is_sorted = true;
+                if curr == "--save-snapshot" {
+                    config.save_snapshot = Some(PathBuf::from(next));
+                } else {
+                    config.diff_snapshot = Some(PathBuf::from(next));
+                }
+            }
+            "--printf" => {
+                match template::Template::compile(next) {
+                    Ok(t) => { config.printf = Some(t); }
+                    Err(e) => {
+                        return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, e));
+                    }
+                }
+            }
+            "--metadata" => {
+                for field in next.split(',') {
+                    let Some(parsed_field) = output::parse_metadata_field(field) else {
+                        return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, format!("invalid option: '{}', provided for --metadata, must be one of: {}", field, output::VALID_METADATA_FIELDS.join(", "))));
+                    };
+                    if !config.metadata_fields.contains(&parsed_field) {
+                        config.metadata_fields.push(parsed_field);
+                    }
+                }
+            }
             _ => {
                 return Err(std::io::Error::new(std::io::ErrorKind::Other, format!("unimplemented arg: '{}'", curr)));
             }
         }
         i += 1;
     }
+
+    match &mut config.delete {
+        Some(delete) => {
+            delete.dry_run = delete_dry_run;
+            delete.force = delete_force;
+        }
+        None if delete_dry_run || delete_force => {
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "`--dry-run` and `--force` can only be used with `--delete`"));
+        }
+        None => {}
+    }
+    // Types are resolved once all the options are read, so `--type-add` can come after the `--type` that uses it
+    if extensions.len() > 0 || type_names.len() > 0 {
+        let mut name_filter = filetype::NameFilter::default();
+        for ext in &extensions {
+            name_filter.add_extension(ext);
+        }
+        if let Err(e) = filetype::resolve_types(&mut name_filter, &type_names, &type_adds) {
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, e));
+        }
+        config.name_filter = Some(name_filter);
+    }
+    // Only files have contents, so directories are filtered out (the same as `--filter f`)
+    match &mut config.contains {
+        Some(contains) => {
+            if config.is_filtered && !config.show_files {
+                return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "`--contains` only matches files, it can't be combined with `--filter d`"));
+            }
+            contains.max_filesize = max_filesize.unwrap_or(contents::DEFAULT_MAX_FILESIZE);
+            contains.search_binary = search_binary;
+            config.is_filtered = true;
+            config.show_dirs = false;
+        }
+        None if config.contains_count || max_filesize.is_some() || search_binary => {
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "`--contains-count`, `--max-filesize` and `--binary` can only be used with `--contains`"));
+        }
+        None => {}
+    }
+    if config.contains.is_some() && (config.watch || config.prune_empty_report) {
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "`--contains` can't be combined with `--watch` or `--prune-empty-report`"));
+    }
+    if config.contains_count && (config.count_only || config.tree || config.printf.is_some()) {
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "`--contains-count` can't be combined with `--count`, `--tree` or `--printf`"));
+    }
+    if config.delete.is_some() && (config.exec.is_some() || config.count_only || config.prune_empty_report || config.tree) {
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "`--delete` can't be combined with `--exec`, `--count`, `--prune-empty-report` or `--tree`"));
+    }
+    if config.db.is_some() && (config.match_empty || config.prune_empty_report || config.one_file_system || config.exclude_fs_types.len() > 0 || config.delete.is_some()) {
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "`--db` can't be combined with `--empty`, `--prune-empty-report`, `--one-file-system`, `--exclude-fs-type` or `--delete`, as they depend on the current state of the filesystem"));
+    }
+    if config.watch && (config.db.is_some() || config.exec.is_some() || config.delete.is_some() || config.count_only || config.prune_empty_report || config.tree || config.match_empty) {
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "`--watch` can't be combined with `--db`, `--exec`, `--delete`, `--count`, `--prune-empty-report`, `--tree` or `--empty`"));
+    }
+    let uses_snapshot = config.save_snapshot.is_some() || config.diff_snapshot.is_some();
+    if uses_snapshot && (config.exec.is_some() || config.delete.is_some() || config.count_only || config.prune_empty_report || config.tree || config.watch) {
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "`--save-snapshot` and `--diff-snapshot` can't be combined with `--exec`, `--delete`, `--count`, `--prune-empty-report`, `--tree` or `--watch`"));
+    }
+    if config.diff_snapshot.is_some() && config.format != output::Format::Text {
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "`--diff-snapshot` can only be used with the 'text' output format"));
+    }
+    // The '+' and '-' markers would make each JSON line invalid
+    if config.watch && config.format != output::Format::Text {
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "`--watch` can only be used with the 'text' output format"));
+    }
+    if config.fuzzy.is_some() && (config.patterns.len() > 0 || config.equality_match || config.invert || config.expr.is_some() || config.tree || config.exec.is_some() || config.delete.is_some() || config.count_only || config.prune_empty_report || config.watch || uses_snapshot) {
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "`--fuzzy` can't be combined with `-e`, `-eq`, `--invert`, `--find`, `--tree`, `--exec`, `--delete`, `--count`, `--prune-empty-report`, `--watch` or snapshots"));
+    }
+    if config.fuzzy.is_none() && (config.fuzzy_full_path || config.top.is_some()) {
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "`--fuzzy-path` and `--top` can only be used with `--fuzzy`"));
+    }
+    if uses_patterns && config.patterns.len() == 0 && config.fuzzy.is_none() {
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "`--patterns-from` didn't contain any patterns"));
+    }
+    if config.pattern_tag.is_some() && (config.patterns.len() == 0 || config.tree || config.printf.is_some() || config.invert) {
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "`--pattern-tag` requires `-e` or `--patterns-from`, and can't be combined with `--tree`, `--printf` or `--invert`"));
+    }
+    if config.expr.is_some() && (config.db.is_some() || config.watch || config.equality_match || config.match_empty || config.prune_empty_report || config.invert || config.name_filter.is_some()) {
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "`--find` can't be combined with `--db`, `--watch`, `-eq`, `--empty`, `--prune-empty-report`, `--invert` (use '!'), `--extension` or `--type` (use '-name')"));
+    }
+    // Only whether anything matched is needed, so nothing has to be sorted. The first match is enough, unless the
+    // matches are only known once the whole tree is walked (`--count`, `--prune-empty-report` and `--fuzzy`)
+    if config.quiet {
+        if config.exec.is_some() || config.delete.is_some() || config.watch || config.pick || config.fuzzy_full_path || uses_snapshot {
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "`--quiet` can't be combined with `--exec`, `--delete`, `--watch`, `--pick`, `--fuzzy-path` or snapshots"));
+        }
+        if !config.count_only && !config.prune_empty_report && config.fuzzy.is_none() {
+            config.max_results = Some(1);
+        }
+        config.is_sorted = false;
+    }
+    if config.max_results.is_some() && (config.count_only || config.prune_empty_report || config.delete.is_some() || config.watch || config.pick || config.fuzzy.is_some() || uses_snapshot) {
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "`--max-results` and `--quit` can't be combined with `--count`, `--prune-empty-report`, `--delete`, `--watch`, `--pick`, `--fuzzy` (use `--top`) or snapshots"));
+    }
+    // A partial walk can't tell which directories are empty
+    if config.timeout.is_some() && (config.db.is_some() || config.watch || config.pick || config.prune_empty_report) {
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "`--timeout` can't be combined with `--db`, `--watch`, `--pick` or `--prune-empty-report`"));
+    }
+    // Picked results are output as plain paths, the picker does its own highlighting
+    if config.pick {
+        if config.is_sorted || config.format != output::Format::Text || config.printf.is_some() || config.label_pos != 0 || config.exec.is_some() || config.delete.is_some() || config.count_only || config.prune_empty_report || config.watch || config.db.is_some() || config.contains_count || config.pattern_tag.is_some() {
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "`--pick` can only be combined with options that select entries (e.g. `--filter`, `-x`, `--contains`), or `--print0` and the path options"));
+        }
+        config.color = color::ColorWhen::Never;
+    }
+    if config.printf.is_some() && config.format != output::Format::Text {
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "`--printf` can only be used with the 'text' output format"));
+    }
+    if config.count_only && config.format != output::Format::Text {
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "`--count` can only be used with the 'text' output format"));
+    }
+    if config.printf.is_some() && config.print0 {
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "`--print0` can't be combined with `--printf`, end the format with '\\0' instead"));
+    }
+    if config.tree && (config.printf.is_some() || config.format != output::Format::Text || config.label_pos != 0) {
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "`--tree` can't be combined with `--printf`, `--label` or the 'json'/'ndjson' output formats"));
+    }
  
     Ok((target, root_pb))    
 }
 
 fn print_help_text() {
+    let type_names = filetype::BUILTIN_TYPES.iter().map(|(name, _)| *name).collect::<Vec<&str>>().join(", ");
     println!("Pretty Fast Find, finds items in your filesystem. It (mostly) performs best with NO optional args.
 
 Usage: pff [options] [pattern] [path]
+       pff [options] --db [index] [pattern]
+       pff [options] -e [pattern] [-e [pattern]...] [path]
+       pff [options] --fuzzy [query] [path]
+       pff [options] --find [path] [expression]
+       pff index build [path] -o [index]
 Optional Arguments:
     --help                                  Prints help
     --version                               Prints version
 
     --include-target                        Include the 'target' directory in the output, if matched
                                             (not included by default)
+    -v, --invert                            Match every entry that DOESN'T match the pattern(s), the root is
+                                            still only included with `--include-target`
+    -x, --extension <ext,...>               Only match entries with one of these extensions (case insensitive),
+                                            checked before the pattern. Use '' as the pattern to match on the
+                                            extension alone, e.g. `pff -x rs,toml '' .`
+    --type <name,...>                       Only match entries in these type groups, one of:
+                                            {type_names}
+    --type-add <name>:<glob,...>            Add globs ('*.<ext>' or a file name) to a type, or define a new one,
+                                            e.g. `--type-add 'proto:*.proto' --type proto`
+    --contains <pattern>                    Only match files whose contents match the (regex) pattern, searched a
+                                            line at a time. Directories are never matched
+    --contains-literal <text>               Same as `--contains`, but matches the text as is
+    --contains-count                        Add the number of matches in each file, after a ':' (or as
+                                            'contains_count' in JSON)
+    --max-filesize <size>  (default: 32M)   Don't search the contents of files larger than this, e.g. '512K'
+    --binary                                Search the contents of binary files too (a NUL byte in their first
+                                            64K), they're skipped by default
+    --pick                                  Choose from the results in an interactive picker as they're found,
+                                            type to (fuzzy) filter them, Up/Down to move, Tab to select several,
+                                            Enter to output the selected (or current) paths and Esc to cancel
+    --fuzzy <query>                         Match basenames containing the query's characters in order (case
+                                            insensitive unless it has an uppercase letter), ranked by how well
+                                            they match, best first (last with `--sort desc`)
+    --fuzzy-path                            Match the `--fuzzy` query against paths (relative to the root)
+                                            instead of basenames
+    --top <num>                             Only output the best 'num' `--fuzzy` matches
     -eq                                     Match EXACTLY on 'pattern', faster than (default) regex check 
                                             for exact matching
+    --empty                                 Only match empty files (zero bytes) and empty directories
+                                            (no entries)
+    --one-file-system, -xdev                Don't descend into directories on a different filesystem to the
+                                            root directory
+    --exclude-fs-type <type,...>            Don't descend into directories on filesystems of these types,
+                                            e.g. 'proc,sysfs,nfs' (see `/proc/self/mountinfo`)
+    --prune-empty-report                    Instead of matches, list the matching directories that contain
+                                            only (recursively) empty directories. Children are listed before
+                                            their parents, e.g. for `xargs rmdir`
 
     --filter <f> [<d> [<s> [<h>]]]          Filter output to just show (f)iles, (d)irectories, (s)ymlinks 
                                             and/or (h)idden files. Providing a 'n' before the parameter 
@@ -271,6 +860,26 @@ Optional Arguments:
                                             NOTE: Sorting reduces performance and increases memory usage, 
                                             'filtering' results can improve this
 
+    --max-results <num>                     Stop once 'num' results are found. With `--sort` the whole tree is
+                                            still searched, but only the first 'num' (sorted) results are kept
+    --timeout <duration>                    Stop reading directories once 'duration' has passed (e.g. '500ms',
+                                            '5s', '2m'), output the results found so far and exit with 3. The
+                                            number of directories that weren't searched is written to stderr.
+                                            A directory read that's stuck (e.g. a dead network mount) is given
+                                            up on a second after the deadline
+    -q, --quiet                             Don't output anything, just exit with 0 if there's a match (stopping
+                                            at the first one), otherwise 1
+    -1, --quit                              Stop after the first result, same as `--max-results 1`
+
+    --count                                 Only output the number of matches in each (filtered) category
+    --stats                                 Write statistics about the search to stderr, e.g. directories read,
+                                            rounds and the work done by each thread, to help tune `-t` and `-fdl`
+
+    --tree [<depth>]                        Output the (sorted) results as an indented tree, including just the
+                                            directories needed to reach them. Entries deeper than 'depth' are
+                                            collapsed into a count, a number straight before the pattern is
+                                            the pattern rather than a depth
+
     --label [<start|end>] (default: start)  Adds a label, at the start or end of each line separated by a
                                             space, indicating the file properties.
 
@@ -280,10 +889,78 @@ Optional Arguments:
                                             NOTE: Labelling can reduce performance and increases memory usage, 
                                             'filtering' results can improve this
     
+    --relative                              Output paths relative to the root directory (the root itself is '.')
+    --absolute                              Output absolute paths, without resolving symlinks
+    --canonical                             Output absolute paths, with symlinks in the root directory resolved
+
+    --color <auto|always|never>             Color entries by their kind (using `LS_COLORS`) and highlight the
+                          (default: auto)   matched part of their name. 'auto' only colors output to a terminal
+
+    -0, --print0                            Terminate each output entry with a NUL instead of a newline, e.g. for
+                                            `xargs -0`. Labels are separated from the path by a tab
+
+    --printf <format>                       Output each entry using a format string instead of its path, no
+                                            newline is added so end the format with '\\n' (or '\\0'), the
+                                            placeholders are:
+                                            {}
+
+    --exec <cmd> ;                          Run a command for each result (in parallel) instead of outputting
+                                            it, the placeholders are:
+                                            {}
+                                            With no placeholders the path is appended to the command
+    --exec-batch <cmd> ;                    Run a command once with all results as arguments (split into more
+                                            runs if they exceed `ARG_MAX`), the placeholder must be a separate
+                                            argument
+
+    --find <path> [expression]              Match using a `find` expression instead of a pattern, it MUST be
+                                            the last argument. Supports: -name, -iname, -type, -size, -mtime,
+                                            -prune, -print, -o, -a, !, ( and ). The root is evaluated too
+
+    -e <pattern>                            Match any of several patterns (repeatable), replacing the
+                                            positional pattern
+    --patterns-from <file>                  Read patterns from a file, one per line, like `-e`
+    --pattern-tag <index|pattern>           Prefix each entry with the index or text of the pattern(s) it
+                                            matched (a 'patterns' field in 'json' and 'ndjson' objects)
+
+    --db <index>                            Search an index built with `pff index build` instead of the
+                                            filesystem, outputting the indexed (canonical) paths
+
+    --watch                                 After the search, watch every directory that was read and output
+                                            matching entries as they're added ('+') or removed ('-'). New
+                                            directories are searched and watched too. Only the 'text' format
+                                            is supported
+
+    --save-snapshot <file>                  Save the (sorted) results to a snapshot file, including their size
+                                            and mtime when they're requested with `--metadata`
+    --diff-snapshot <file>                  Only output the entries that were added ('+'), removed ('-') or
+                                            changed ('~') since a snapshot, searched from the same root
+
+    --delete                                Delete the results once the walk is finished, children before their
+                                            parents. Directories that still contain entries aren't deleted
+    --dry-run                               With `--delete`, output what would be deleted (in order) instead
+    --force                                 With `--delete`, allow an empty pattern without any filter (e.g.
+                                            `--filter`, `--type` or `--contains`) and deleting the root directory
+
+    --format <text|json|ndjson>             Output format. 'json' writes a single (sorted) array, 'ndjson' writes
+                          (default: text)   one object per line as results are found. Each object has the 'path',
+                                            'kind' (file|dir), 'hidden', 'symlink' and 'depth' of the entry.
+                                            Paths that aren't valid UTF-8 are written as an object with their
+                                            base64 encoded bytes, e.g. {{\"bytes\":\"L3RtcC9m/w==\"}}
+    --metadata <field,...>                  Include metadata in 'json' and 'ndjson' objects (and snapshots),
+                                            any of: size, mtime, mode, inode, uid, gid
+
     -t   <num>            (default:    {})  Specify the number of threads, MUST BE >= 2
     -fdl <num>            (default:  {})  Specify the maximum 'files + dirs' to traverse before returning
                                             results from each thread
 
-", DEFAULT_NUM_THREADS, DEFAULT_FD_LIMIT);
+Exit Status:
+    0    At least one entry matched (or `index` succeeded)
+    1    Nothing matched, or nothing was chosen with `--pick`
+    2    Invalid arguments, the root (or an index) couldn't be read, or an `--exec`/`--delete` failed.
+         Directories that can't be read below the root are skipped, the results are still output (with
+         `--quiet`, a match still exits with 0)
+    3    `--timeout` expired before the search finished, the results are partial
+
+", template::PLACEHOLDER_HELP, exec::PLACEHOLDER_HELP, DEFAULT_NUM_THREADS, DEFAULT_FD_LIMIT);
 }
 
@@ -1,6 +1,8 @@
+use std::path::PathBuf;
+
 pub const NUM_FILE_CATEGORIES: usize = 6;
 
-// matches, are stored in a Vec<String> where indexes from the root represents entries with different properties:
+// matches, are stored in a Vec<PathBuf> where indexes from the root represents entries with different properties:
 // 0 -> not hidden / file
 // 1 -> not hidden / symlink
 // 2 -> not hidden / directory
@@ -10,11 +12,12 @@ pub const NUM_FILE_CATEGORIES: usize = 6;
 
 // initialise_matches_capacities, initialises the vector capacities based on the sample directory used in `BEMCHARKS.md`
 // TODO: Occurence ratios below could be more generalised
-pub fn initialise_matches_capacities(fd_limit: usize) -> [Vec<String>; NUM_FILE_CATEGORIES] {
-    let mut matches: [Vec<String>; NUM_FILE_CATEGORIES] = [const { Vec::new() }; NUM_FILE_CATEGORIES];
+#[allow(clippy::needless_range_loop)]
+pub fn initialise_matches_capacities(fd_limit: usize) -> [Vec<PathBuf>; NUM_FILE_CATEGORIES] {
+    let mut matches: [Vec<PathBuf>; NUM_FILE_CATEGORIES] = [const { Vec::new() }; NUM_FILE_CATEGORIES];
     let mut left = fd_limit;
-    let type_mults = vec![92.5/100.0, 1.0/100.0, 6.5/100.0];
-    let hidden_mults = vec![99.9/100.0, 0.1/100.0];
+    let type_mults = [92.5/100.0, 1.0/100.0, 6.5/100.0];
+    let hidden_mults = [99.9/100.0, 0.1/100.0];
     for i in 0..NUM_FILE_CATEGORIES {
         let file_type_idx = i % 3;
         let is_hidden_idx = (i >= 3) as usize;
@@ -28,11 +31,16 @@ pub fn initialise_matches_capacities(fd_limit: usize) -> [Vec<String>; NUM_FILE_
     return matches;
 }
 
-// insert_entry_in_matches, inserts an entry in `matches` at the index that corresponds to its properties according to the following formula:
+// category_index, the index in `matches` that corresponds to an entry's properties according to the following formula:
 //  (is_hidden * 3) + (IS_FILE ? 0 : (IS_SYMLINK ? 1 : 2))
-pub fn insert_entry_in_matches(matches: &mut [Vec<String>; NUM_FILE_CATEGORIES], ent: String, hidden: bool, file: bool, symlink: bool) {
+pub fn category_index(hidden: bool, file: bool, symlink: bool) -> usize {
     let hidden_type_offset = hidden as usize * 3;
     let entry_type_offset = (symlink as usize) + ((!file as usize) * 2);
-    let idx = hidden_type_offset + entry_type_offset;
-    matches[idx].push(ent);
-}
\ No newline at end of file
+    return hidden_type_offset + entry_type_offset;
+}
+
+// category_properties, the inverse of `category_index`, returns (is_hidden, is_file, is_symlink) for an index
+pub fn category_properties(idx: usize) -> (bool, bool, bool) {
+    let file_type_idx = idx % 3;
+    return (idx >= 3, file_type_idx != 2, file_type_idx == 1);
+}
@@ -0,0 +1,65 @@
+use std::collections::HashSet;
+use std::os::unix::fs::MetadataExt;
+use std::path::Path;
+
+const MOUNTINFO_PATH: &str = "/proc/self/mountinfo";
+
+// DeviceFilter, determines which directories are on a filesystem that shouldn't be descended into, based on their device id
+pub struct DeviceFilter {
+    root_dev: Option<u64>,
+    excluded_devs: HashSet<u64>,
+}
+
+impl DeviceFilter {
+    pub fn new(root: &Path, one_file_system: bool, exclude_fs_types: &[String]) -> std::io::Result<DeviceFilter> {
+        let mut root_dev = None;
+        if one_file_system {
+            root_dev = Some(std::fs::metadata(root)?.dev());
+        }
+
+        let mut excluded_devs = HashSet::new();
+        if exclude_fs_types.len() > 0 {
+            let mountinfo = std::fs::read_to_string(MOUNTINFO_PATH)?;
+            excluded_devs = devices_with_fs_types(&mountinfo, exclude_fs_types);
+        }
+        return Ok(DeviceFilter { root_dev, excluded_devs });
+    }
+
+    pub fn is_active(&self) -> bool {
+        return self.root_dev.is_some() || self.excluded_devs.len() > 0;
+    }
+
+    pub fn is_excluded(&self, dev: u64) -> bool {
+        if let Some(root_dev) = self.root_dev {
+            if dev != root_dev {
+                return true;
+            }
+        }
+        return self.excluded_devs.contains(&dev);
+    }
+}
+
+// devices_with_fs_types, finds the device ids of mounts with any of the `fs_types`, each line of `mountinfo` looks like:
+//  36 35 98:0 /mnt1 /mnt/parent rw,noatime master:1 - ext3 /dev/root rw,errors=continue
+// where the 3rd field is the "major:minor" device id and the filesystem type follows the " - " separator
+fn devices_with_fs_types(mountinfo: &str, fs_types: &[String]) -> HashSet<u64> {
+    let mut ret = HashSet::new();
+    for line in mountinfo.lines() {
+        let Some((mount_fields, fs_fields)) = line.split_once(" - ") else { continue };
+        let Some(fs_type) = fs_fields.split(' ').next() else { continue };
+        if !fs_types.iter().any(|t| t == fs_type) {
+            continue;
+        }
+
+        let Some(dev_field) = mount_fields.split(' ').nth(2) else { continue };
+        let Some((major, minor)) = dev_field.split_once(':') else { continue };
+        let (Ok(major), Ok(minor)) = (major.parse::<u64>(), minor.parse::<u64>()) else { continue };
+        ret.insert(make_dev(major, minor));
+    }
+    return ret;
+}
+
+// make_dev, encodes a major/minor pair the same way as glibc's `makedev`, so it can be compared with `st_dev`
+fn make_dev(major: u64, minor: u64) -> u64 {
+    return ((major & 0xfffff000) << 32) | ((major & 0x00000fff) << 8) | ((minor & 0xffffff00) << 12) | (minor & 0x000000ff);
+}
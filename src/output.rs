@@ -0,0 +1,391 @@
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::MetadataExt;
+use std::io::{IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, Mutex};
+use regex::bytes::{Regex, RegexSet};
+
+use crate::color::{ColorWhen, Colors};
+use crate::label;
+use crate::matches;
+use crate::walk::WalkCtx;
+use crate::Config;
+
+const BASE64_CHARS: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+#[derive(PartialEq, Clone, Copy)]
+pub enum Format {
+    Text,
+    Json,
+    Ndjson,
+}
+
+// PathMode, how output paths are written, by default they're joined onto the root as it was provided
+#[derive(PartialEq, Clone, Copy)]
+pub enum PathMode {
+    AsProvided,
+    Relative,
+    Absolute,
+    Canonical,
+}
+
+// PatternTag, how the pattern(s) an entry matched are identified with `--pattern-tag`
+#[derive(PartialEq, Clone, Copy)]
+pub enum PatternTag {
+    Index,
+    Pattern,
+}
+
+#[derive(PartialEq, Clone, Copy)]
+pub enum MetadataField {
+    Size,
+    Mtime,
+    Mode,
+    Inode,
+    Uid,
+    Gid,
+}
+
+pub const VALID_METADATA_FIELDS: [&str; 6] = ["size", "mtime", "mode", "inode", "uid", "gid"];
+
+pub fn parse_metadata_field(s: &str) -> Option<MetadataField> {
+    match s {
+        "size" => Some(MetadataField::Size),
+        "mtime" => Some(MetadataField::Mtime),
+        "mode" => Some(MetadataField::Mode),
+        "inode" => Some(MetadataField::Inode),
+        "uid" => Some(MetadataField::Uid),
+        "gid" => Some(MetadataField::Gid),
+        _ => None,
+    }
+}
+
+// Renderer, turns matched paths into output lines. It's shared between the walker threads so that formatting happens in
+// parallel, before they're output
+pub struct Renderer<'a> {
+    cfg: &'a Config,
+    root: PathBuf,
+    root_depth: usize,
+    output_root: Option<PathBuf>,
+    colors: Option<Colors>,
+    match_rx: Regex,
+    match_exact: Option<String>,
+    match_set: Option<RegexSet>,
+    content_counts: Arc<Mutex<HashMap<PathBuf, usize>>>,
+    sink: Option<Sender<Vec<Vec<u8>>>>,
+    is_sink_closed: Arc<AtomicBool>,
+}
+
+// Sink, where unsorted results are sent as they're found instead of being printed (e.g. `--pick`). `is_closed` is set
+// once they're no longer wanted, either by the receiver or when sending fails, and stops the walk
+pub struct Sink {
+    pub tx: Sender<Vec<Vec<u8>>>,
+    pub is_closed: Arc<AtomicBool>,
+}
+
+impl Renderer<'_> {
+    pub fn new<'a>(cfg: &'a Config, root: &Path, walk_ctx: &WalkCtx) -> std::io::Result<Renderer<'a>> {
+        // The root's replacement is only resolved once, each output path then just swaps its prefix
+        let output_root = match cfg.path_mode {
+            PathMode::AsProvided => None,
+            PathMode::Relative => Some(PathBuf::new()),
+            PathMode::Absolute => Some(std::path::absolute(root)?),
+            PathMode::Canonical => Some(std::fs::canonicalize(root)?),
+        };
+
+        // Colors are only used for 'text' output, 'auto' only colors output to a terminal (unless `NO_COLOR` is set)
+        let use_color = match cfg.color {
+            ColorWhen::Always => true,
+            ColorWhen::Never => false,
+            ColorWhen::Auto => std::io::stdout().is_terminal() && std::env::var_os("NO_COLOR").is_none(),
+        };
+        let mut colors = None;
+        if use_color && cfg.format == Format::Text && cfg.printf.is_none() {
+            colors = Some(Colors::from_env());
+        }
+        return Ok(Renderer { cfg, root: root.to_path_buf(), root_depth: root.components().count(), output_root, colors, match_rx: walk_ctx.match_rx.clone(), match_exact: walk_ctx.match_exact.clone(), match_set: walk_ctx.match_set.clone(), content_counts: walk_ctx.content_counts.clone(), sink: None, is_sink_closed: Arc::new(AtomicBool::new(false)) });
+    }
+
+    // with_sink, unsorted results are sent to `sink` (e.g. `--pick`) as they're found, instead of being printed
+    pub fn with_sink(mut self, sink: Option<Sink>) -> Self {
+        if let Some(sink) = sink {
+            self.sink = Some(sink.tx);
+            self.is_sink_closed = sink.is_closed;
+        }
+        return self;
+    }
+
+    // output, prints rendered results or sends them to the sink. Once the sink's receiver is gone nothing else is wanted,
+    // so the walk can stop early (see `is_sink_closed`)
+    pub fn output(&self, results: Vec<Vec<u8>>) {
+        let Some(sink) = &self.sink else {
+            if !self.cfg.quiet {
+                print_results(self.cfg, &results);
+            }
+            return;
+        };
+        if results.len() > 0 && sink.send(results).is_err() {
+            self.is_sink_closed.store(true, Ordering::Relaxed);
+        }
+    }
+
+    pub fn is_sink_closed(&self) -> bool {
+        return self.is_sink_closed.load(Ordering::Relaxed);
+    }
+
+    // output_path, swaps the root prefix of `path` based on the `PathMode`, keeping the trailing '/' of directories
+    pub fn output_path<'p>(&self, path: &'p Path) -> Cow<'p, Path> {
+        let Some(output_root) = &self.output_root else {
+            return Cow::Borrowed(path);
+        };
+        let Ok(rest) = path.strip_prefix(&self.root) else {
+            return Cow::Borrowed(path);
+        };
+
+        let mut ret = output_root.join(rest).into_os_string();
+        if ret.len() == 0 {
+            ret.push(".");
+        }
+        if path.as_os_str().as_bytes().ends_with(b"/") && !ret.as_bytes().ends_with(b"/") {
+            ret.push("/");
+        }
+        return Cow::Owned(PathBuf::from(ret));
+    }
+
+    // depth, the number of components below the root, e.g. the root's children have a depth of 1
+    fn depth(&self, path: &Path) -> usize {
+        return path.components().count().saturating_sub(self.root_depth);
+    }
+
+    // render, outputs are raw bytes, so paths that aren't valid UTF-8 are written unchanged
+    pub fn render(&self, path: &Path, category: usize) -> Vec<u8> {
+        let (is_hidden, is_file, is_symlink) = matches::category_properties(category);
+        if let Some(template) = &self.cfg.printf {
+            return template.render(path, &self.output_path(path), category, &self.root, self.depth(path));
+        } else if self.cfg.format != Format::Text {
+            return self.render_json(path, is_hidden, is_file, is_symlink).into_bytes();
+        }
+
+        // NUL separated output uses a tab between the label and path, so it can be split unambiguously
+        let sep = if self.cfg.print0 { b'\t' } else { b' ' };
+        let mut line = self.render_text(path, sep, is_hidden, is_file, is_symlink);
+        if let Some(count) = self.contains_count(path) {
+            line.extend_from_slice(format!(":{}", count).as_bytes());
+        }
+        let Some(pattern_idxs) = self.matched_patterns(path) else {
+            return line;
+        };
+        let tags: Vec<Vec<u8>> = pattern_idxs.iter().map(|idx| match self.cfg.pattern_tag {
+            Some(PatternTag::Pattern) => self.cfg.patterns[*idx].as_bytes().to_vec(),
+            _ => idx.to_string().into_bytes(),
+        }).collect();
+        return [tags.join(&b","[..]), vec![sep], line].concat();
+    }
+
+    fn render_text(&self, path: &Path, sep: u8, is_hidden: bool, is_file: bool, is_symlink: bool) -> Vec<u8> {
+        let out_path = self.output_path(path);
+        let path_bytes = out_path.as_os_str().as_bytes();
+        if let Some(colors) = &self.colors {
+            // The match is only highlighted if the output path kept the walked basename, `--relative` prints the root as
+            // './' and `--canonical` resolves symlinks, so the span may not apply to what's printed
+            let mut match_span = None;
+            if out_path.file_name() == path.file_name() {
+                match_span = self.match_span(path);
+            }
+            let colored_path = colors.colorize(&out_path, match_span, is_file, is_symlink);
+            if self.cfg.label_pos == 0 {
+                return colored_path;
+            }
+            let colored_label = colors.colorize_label(&label::generate_label(is_hidden, is_file, is_symlink), is_file, is_symlink);
+            return label::join_label(&colored_path, &colored_label, self.cfg.label_pos, sep);
+        }
+
+        if self.cfg.label_pos != 0 {
+            return label::add_label(path_bytes, self.cfg.label_pos, sep, is_hidden, is_file, is_symlink);
+        }
+        return path_bytes.to_vec();
+    }
+
+    // render_tree_name, renders the name of a `--tree` entry, only matched entries have their match highlighted
+    pub fn render_tree_name(&self, name: &OsStr, is_match: bool, is_file: bool, is_symlink: bool) -> Vec<u8> {
+        let Some(colors) = &self.colors else {
+            return name.as_bytes().to_vec();
+        };
+        let mut match_span = None;
+        if is_match {
+            match_span = self.match_span(Path::new(name));
+        }
+        return colors.colorize(Path::new(name), match_span, is_file, is_symlink);
+    }
+
+    // match_span, the start and end of the pattern's match in the basename of `path`
+    fn match_span(&self, path: &Path) -> Option<(usize, usize)> {
+        // Inverted matches didn't match the pattern, so there's nothing to highlight
+        if self.cfg.invert {
+            return None;
+        }
+        let base_name = path.file_name()?.as_bytes();
+        if self.match_exact.is_some() {
+            return Some((0, base_name.len()));
+        }
+        // An empty match (e.g. the pattern of an expression) isn't highlighted
+        return self.match_rx.find(base_name).filter(|m| m.end() > m.start()).map(|m| (m.start(), m.end()));
+    }
+
+    // matched_patterns, the indexes of the patterns (`-e`) the basename of `path` matched, with `--pattern-tag`
+    fn matched_patterns(&self, path: &Path) -> Option<Vec<usize>> {
+        let (Some(_), Some(match_set)) = (self.cfg.pattern_tag, &self.match_set) else {
+            return None;
+        };
+        let base_name = path.file_name().unwrap_or_default().as_bytes();
+        return Some(match_set.matches(base_name).into_iter().collect());
+    }
+
+    // contains_count, the number of `--contains` matches in the file at `path`, with `--contains-count`. They're counted
+    // by the walk, a file that wasn't (e.g. one added while `--watch`ing) is read here
+    fn contains_count(&self, path: &Path) -> Option<usize> {
+        if !self.cfg.contains_count {
+            return None;
+        }
+        if let Some(count) = self.content_counts.lock().unwrap().remove(path) {
+            return Some(count);
+        }
+        return self.cfg.contains.as_ref().map(|contains| contains.num_matches(path, true));
+    }
+
+    pub fn render_categorised(&self, categorised: Vec<(usize, Vec<PathBuf>)>) -> Vec<Vec<u8>> {
+        let mut ret = Vec::with_capacity(categorised.iter().map(|(_, entries)| entries.len()).sum());
+        for (category, entries) in categorised {
+            for ent in entries {
+                ret.push(self.render(&ent, category));
+            }
+        }
+        return ret;
+    }
+
+    fn render_json(&self, path: &Path, is_hidden: bool, is_file: bool, is_symlink: bool) -> String {
+        let mut ret = String::from("{\"path\":");
+        push_json_path(&mut ret, &self.output_path(path));
+        ret.push_str(if is_file { ",\"kind\":\"file\"" } else { ",\"kind\":\"dir\"" });
+        ret.push_str(&format!(",\"hidden\":{},\"symlink\":{},\"depth\":{}", is_hidden, is_symlink, self.depth(path)));
+        if let Some(pattern_idxs) = self.matched_patterns(path) {
+            let tag_values: Vec<String> = pattern_idxs.iter().map(|idx| match self.cfg.pattern_tag {
+                Some(PatternTag::Pattern) => {
+                    let mut quoted = String::new();
+                    push_json_string(&mut quoted, &self.cfg.patterns[*idx]);
+                    quoted
+                }
+                _ => idx.to_string(),
+            }).collect();
+            ret.push_str(&format!(",\"patterns\":[{}]", tag_values.join(",")));
+        }
+        if let Some(count) = self.contains_count(path) {
+            ret.push_str(&format!(",\"contains_count\":{}", count));
+        }
+
+        if self.cfg.metadata_fields.len() > 0 {
+            match std::fs::symlink_metadata(path) {
+                Ok(md) => {
+                    for field in &self.cfg.metadata_fields {
+                        let (name, val) = match field {
+                            MetadataField::Size => ("size", md.size() as i64),
+                            MetadataField::Mtime => ("mtime", md.mtime()),
+                            MetadataField::Mode => ("mode", md.mode() as i64),
+                            MetadataField::Inode => ("inode", md.ino() as i64),
+                            MetadataField::Uid => ("uid", md.uid() as i64),
+                            MetadataField::Gid => ("gid", md.gid() as i64),
+                        };
+                        ret.push_str(&format!(",\"{}\":{}", name, val));
+                    }
+                }
+                Err(e) => {
+                    ret.push_str(",\"error\":");
+                    push_json_string(&mut ret, &e.to_string());
+                }
+            }
+        }
+        ret.push('}');
+        return ret;
+    }
+}
+
+fn print_results(cfg: &Config, results: &[Vec<u8>]) {
+    if results.len() == 0 {
+        return;
+    }
+
+    let output_bytes = join_results(cfg, results);
+    let _ = std::io::stdout().lock().write_all(&output_bytes);
+}
+
+// join_results, joins rendered entries into the final output, each entry is terminated by a newline (or NUL with
+// `--print0`) and `json` output is wrapped in an array. `--printf` entries aren't terminated, like find's `-printf`
+// the format string is expected to include its own terminator
+pub fn join_results(cfg: &Config, results: &[Vec<u8>]) -> Vec<u8> {
+    if cfg.format == Format::Json {
+        if results.len() == 0 {
+            return b"[]\n".to_vec();
+        }
+        return [b"[\n".to_vec(), results.join(&b",\n"[..]), b"\n]\n".to_vec()].concat();
+    }
+
+    if cfg.printf.is_some() {
+        return results.concat();
+    }
+
+    let terminator = if cfg.print0 { b'\0' } else { b'\n' };
+    let mut ret = Vec::with_capacity(results.iter().map(|r| r.len() + 1).sum());
+    for r in results {
+        ret.extend_from_slice(r);
+        ret.push(terminator);
+    }
+    return ret;
+}
+
+// push_json_path, paths are output as a JSON string when they're valid UTF-8, otherwise as an object containing their
+// base64 encoded bytes, e.g. {"bytes":"L3RtcC9m/w=="}
+fn push_json_path(out: &mut String, path: &Path) {
+    match path.to_str() {
+        Some(s) => push_json_string(out, s),
+        None => {
+            out.push_str("{\"bytes\":\"");
+            push_base64(out, path.as_os_str().as_bytes());
+            out.push_str("\"}");
+        }
+    }
+}
+
+fn push_json_string(out: &mut String, s: &str) {
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+fn push_base64(out: &mut String, bytes: &[u8]) {
+    for chunk in bytes.chunks(3) {
+        let b = [chunk[0], *chunk.get(1).unwrap_or(&0), *chunk.get(2).unwrap_or(&0)];
+        let n = ((b[0] as u32) << 16) | ((b[1] as u32) << 8) | (b[2] as u32);
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(BASE64_CHARS[((n >> (18 - (i * 6))) & 0x3f) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+}
@@ -0,0 +1,335 @@
+use rayon::iter::{IndexedParallelIterator, IntoParallelRefIterator, ParallelIterator};
+use rayon::slice::ParallelSliceMut;
+use std::collections::BTreeSet;
+use std::io::{Read, Write};
+use std::os::unix::io::AsRawFd;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::sync::Arc;
+
+use crate::find;
+use crate::fuzzy::FuzzyQuery;
+use crate::output::Sink;
+use crate::Config;
+
+// How often new results are drawn while the walk is running (and the terminal size is checked)
+const POLL_INTERVAL_MS: i32 = 50;
+// The prompt and status lines above the results
+const HEADER_LINES: usize = 2;
+
+// RawTerminal, the controlling terminal in raw mode on the alternate screen, both are restored when it's dropped. The UI
+// is drawn on the terminal rather than stdout, so the selection can be piped or substituted, e.g. `vim $(pff --pick ...)`
+struct RawTerminal {
+    tty: std::fs::File,
+    orig: libc::termios,
+}
+
+impl RawTerminal {
+    fn new() -> std::io::Result<RawTerminal> {
+        let tty = std::fs::OpenOptions::new().read(true).write(true).open("/dev/tty")?;
+        let mut orig: libc::termios = unsafe { std::mem::zeroed() };
+        if unsafe { libc::tcgetattr(tty.as_raw_fd(), &mut orig) } != 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+
+        // Keys are read as they're pressed without being echoed, and Ctrl-C is read as a key rather than a signal
+        let mut raw = orig;
+        raw.c_lflag &= !(libc::ICANON | libc::ECHO | libc::ISIG | libc::IEXTEN);
+        raw.c_iflag &= !(libc::IXON | libc::ICRNL);
+        raw.c_cc[libc::VMIN] = 0;
+        raw.c_cc[libc::VTIME] = 0;
+        if unsafe { libc::tcsetattr(tty.as_raw_fd(), libc::TCSANOW, &raw) } != 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        let mut ret = RawTerminal { tty, orig };
+        ret.tty.write_all(b"\x1b[?1049h")?;
+        return Ok(ret);
+    }
+
+    // size, the (rows, columns) of the terminal
+    fn size(&self) -> (usize, usize) {
+        let mut ws: libc::winsize = unsafe { std::mem::zeroed() };
+        if unsafe { libc::ioctl(self.tty.as_raw_fd(), libc::TIOCGWINSZ, &mut ws) } != 0 || ws.ws_row == 0 || ws.ws_col == 0 {
+            return (24, 80);
+        }
+        return (ws.ws_row as usize, ws.ws_col as usize);
+    }
+
+    // wait_for_input, whether there's input to read within `timeout_ms`
+    fn wait_for_input(&self, timeout_ms: i32) -> bool {
+        let mut pfd = libc::pollfd { fd: self.tty.as_raw_fd(), events: libc::POLLIN, revents: 0 };
+        return unsafe { libc::poll(&mut pfd, 1, timeout_ms) } > 0;
+    }
+}
+
+impl Drop for RawTerminal {
+    fn drop(&mut self) {
+        let _ = self.tty.write_all(b"\x1b[?1049l");
+        let _ = self.tty.flush();
+        unsafe { libc::tcsetattr(self.tty.as_raw_fd(), libc::TCSANOW, &self.orig) };
+    }
+}
+
+enum Key {
+    Char(u8),
+    Backspace,
+    ClearQuery,
+    DeleteWord,
+    Up,
+    Down,
+    PageUp,
+    PageDown,
+    Toggle,
+    ToggleUp,
+    Accept,
+    Abort,
+}
+
+// Picker, the results found so far and the ones that match the query, as (score, result index) sorted by score
+struct Picker {
+    items: Vec<Vec<u8>>,
+    query: Vec<u8>,
+    filtered: Vec<(i64, usize)>,
+    num_scored: usize,
+    cursor: usize,
+    offset: usize,
+    selected: BTreeSet<usize>,
+    is_walking: bool,
+}
+
+impl Picker {
+    // update_filter, only the results that arrived since the last update are scored, unless the query changed (which
+    // resets `num_scored`). Without a query results are kept in the order they were found
+    fn update_filter(&mut self) {
+        if self.num_scored == self.items.len() {
+            return;
+        }
+        if self.query.len() == 0 {
+            self.filtered.extend((self.num_scored..self.items.len()).map(|idx| (0, idx)));
+        } else {
+            let fuzzy = FuzzyQuery::new(&String::from_utf8_lossy(&self.query));
+            let first_idx = self.num_scored;
+            let new_matches: Vec<(i64, usize)> = self.items[first_idx..].par_iter().enumerate().filter_map(|(idx, item)| {
+                return fuzzy.score(item).map(|score| (score, first_idx + idx));
+            }).collect();
+            self.filtered.extend(new_matches);
+            let items = &self.items;
+            self.filtered.par_sort_by(|a, b| {
+                return b.0.cmp(&a.0).then_with(|| items[a.1].len().cmp(&items[b.1].len())).then_with(|| a.1.cmp(&b.1));
+            });
+        }
+        self.num_scored = self.items.len();
+        self.cursor = self.cursor.min(self.filtered.len().saturating_sub(1));
+    }
+
+    fn set_query(&mut self, query: Vec<u8>) {
+        self.query = query;
+        self.filtered.clear();
+        self.num_scored = 0;
+        self.cursor = 0;
+        self.offset = 0;
+        self.update_filter();
+    }
+
+    fn move_cursor(&mut self, delta: isize) {
+        let last = self.filtered.len().saturating_sub(1) as isize;
+        self.cursor = (self.cursor as isize + delta).clamp(0, last) as usize;
+    }
+
+    fn toggle(&mut self) {
+        let Some((_, idx)) = self.filtered.get(self.cursor) else { return };
+        if !self.selected.remove(idx) {
+            self.selected.insert(*idx);
+        }
+    }
+
+    // selection, the selected results in the order they were found, or the one under the cursor if none were selected
+    fn selection(mut self) -> Vec<Vec<u8>> {
+        if self.selected.len() == 0 {
+            return self.filtered.get(self.cursor).map(|(_, idx)| std::mem::take(&mut self.items[*idx])).into_iter().collect();
+        }
+        return self.selected.iter().map(|idx| std::mem::take(&mut self.items[*idx])).collect();
+    }
+
+    fn draw(&mut self, term: &mut RawTerminal) -> std::io::Result<()> {
+        let (rows, cols) = term.size();
+        let height = rows.saturating_sub(HEADER_LINES).max(1);
+        if self.cursor < self.offset {
+            self.offset = self.cursor;
+        } else if self.cursor >= self.offset + height {
+            self.offset = self.cursor + 1 - height;
+        }
+
+        let query = String::from_utf8_lossy(&self.query);
+        let mut frame = format!("\x1b[H> {}\x1b[K\r\n", query);
+        let walking = if self.is_walking { " (searching)" } else { "" };
+        let selected = if self.selected.len() > 0 { format!(" [{} selected]", self.selected.len()) } else { String::new() };
+        frame.push_str(&format!("  {}/{}{}{}\x1b[K", self.filtered.len(), self.items.len(), walking, selected));
+        for (pos, (_, idx)) in self.filtered.iter().enumerate().skip(self.offset).take(height) {
+            let is_cursor = pos == self.cursor;
+            let marker = if is_cursor { '>' } else { ' ' };
+            let select_marker = if self.selected.contains(idx) { '*' } else { ' ' };
+            let text: String = String::from_utf8_lossy(&self.items[*idx]).chars().map(|c| if c.is_control() { '?' } else { c }).take(cols.saturating_sub(3)).collect();
+            if is_cursor {
+                frame.push_str(&format!("\r\n\x1b[7m{}{}{}\x1b[0m\x1b[K", marker, select_marker, text));
+            } else {
+                frame.push_str(&format!("\r\n{}{}{}\x1b[K", marker, select_marker, text));
+            }
+        }
+        frame.push_str(&format!("\x1b[J\x1b[1;{}H", 3 + query.chars().count()));
+        term.tty.write_all(frame.as_bytes())?;
+        return term.tty.flush();
+    }
+}
+
+// pick, walks the tree in the background while the results are shown in a terminal UI, where they're filtered (with a
+// fuzzy query) as it's typed. The selected results are the output, there are none if it's aborted (Esc or Ctrl-C)
+pub fn pick(target: String, root: PathBuf, cfg: &Config) -> std::io::Result<find::FindResult> {
+    let (tx, rx) = mpsc::channel();
+    let is_closed = Arc::new(AtomicBool::new(false));
+    let sink = Sink { tx, is_closed: is_closed.clone() };
+    return std::thread::scope(|s| {
+        let walker = s.spawn(move || find::find_with_walked_dirs(target, root, cfg, Some(sink)));
+        // The walk is stopped as soon as the UI exits, rather than when it next tries to send results
+        let selection = run_ui(rx);
+        is_closed.store(true, Ordering::Relaxed);
+        let walk_result = walker.join().unwrap_or_else(|_| Err(std::io::Error::new(std::io::ErrorKind::Other, "the search failed")));
+        let selection = selection?;
+        let num_errors = walk_result?.num_errors;
+        return Ok(find::FindResult { num_matches: selection.len(), output: selection, walked_dirs: Vec::new(), num_unvisited_dirs: None, num_errors });
+    });
+}
+
+fn run_ui(rx: Receiver<Vec<Vec<u8>>>) -> std::io::Result<Vec<Vec<u8>>> {
+    let mut term = RawTerminal::new()?;
+    let mut picker = Picker { items: Vec::new(), query: Vec::new(), filtered: Vec::new(), num_scored: 0, cursor: 0, offset: 0, selected: BTreeSet::new(), is_walking: true };
+    let mut last_size = (0, 0);
+    let mut buf = [0u8; 1024];
+    loop {
+        let mut needs_draw = term.size() != last_size;
+        while picker.is_walking {
+            match rx.try_recv() {
+                Ok(lines) => {
+                    picker.items.extend(lines);
+                    needs_draw = true;
+                }
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => {
+                    picker.is_walking = false;
+                    needs_draw = true;
+                }
+            }
+        }
+        if needs_draw {
+            picker.update_filter();
+            picker.draw(&mut term)?;
+            last_size = term.size();
+        }
+
+        if !term.wait_for_input(POLL_INTERVAL_MS) {
+            continue;
+        }
+        let num_read = match term.tty.read(&mut buf) {
+            Ok(n) => n,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        for key in parse_keys(&buf[..num_read]) {
+            let page = term.size().0.saturating_sub(HEADER_LINES).max(1) as isize;
+            match key {
+                Key::Char(b) => {
+                    let mut query = std::mem::take(&mut picker.query);
+                    query.push(b);
+                    picker.set_query(query);
+                }
+                Key::Backspace => {
+                    // Removes a whole UTF-8 character, i.e. any continuation bytes and the byte that starts it
+                    let mut query = std::mem::take(&mut picker.query);
+                    while let Some(b) = query.pop() {
+                        if b & 0xc0 != 0x80 {
+                            break;
+                        }
+                    }
+                    picker.set_query(query);
+                }
+                Key::ClearQuery => picker.set_query(Vec::new()),
+                Key::DeleteWord => {
+                    let mut query = std::mem::take(&mut picker.query);
+                    while query.last() == Some(&b' ') {
+                        query.pop();
+                    }
+                    while query.last().is_some_and(|b| *b != b' ') {
+                        query.pop();
+                    }
+                    picker.set_query(query);
+                }
+                Key::Up => picker.move_cursor(-1),
+                Key::Down => picker.move_cursor(1),
+                Key::PageUp => picker.move_cursor(-page),
+                Key::PageDown => picker.move_cursor(page),
+                Key::Toggle => {
+                    picker.toggle();
+                    picker.move_cursor(1);
+                }
+                Key::ToggleUp => {
+                    picker.toggle();
+                    picker.move_cursor(-1);
+                }
+                Key::Accept => {
+                    drop(term);
+                    return Ok(picker.selection());
+                }
+                Key::Abort => return Ok(Vec::new()),
+            }
+        }
+        picker.draw(&mut term)?;
+    }
+}
+
+// parse_keys, escape sequences are only recognised when they're read together, a lone Esc aborts
+fn parse_keys(bytes: &[u8]) -> Vec<Key> {
+    let mut ret = Vec::new();
+    let mut i = 0;
+    while i < bytes.len() {
+        let b = bytes[i];
+        i += 1;
+        let key = match b {
+            0x1b => {
+                let seq = &bytes[i..];
+                let (key, len) = if seq.starts_with(b"[A") || seq.starts_with(b"OA") {
+                    (Key::Up, 2)
+                } else if seq.starts_with(b"[B") || seq.starts_with(b"OB") {
+                    (Key::Down, 2)
+                } else if seq.starts_with(b"[5~") {
+                    (Key::PageUp, 3)
+                } else if seq.starts_with(b"[6~") {
+                    (Key::PageDown, 3)
+                } else if seq.starts_with(b"[Z") {
+                    (Key::ToggleUp, 2)
+                } else if seq.len() == 0 {
+                    (Key::Abort, 0)
+                } else {
+                    // Any other sequence is skipped, up to its final byte
+                    let len = seq.iter().skip(1).position(|c| (0x40..=0x7e).contains(c)).map_or(seq.len(), |pos| pos + 2);
+                    i += len;
+                    continue;
+                };
+                i += len;
+                key
+            }
+            0x03 | 0x07 => Key::Abort,
+            b'\r' | b'\n' => Key::Accept,
+            b'\t' => Key::Toggle,
+            0x7f | 0x08 => Key::Backspace,
+            0x15 => Key::ClearQuery,
+            0x17 => Key::DeleteWord,
+            0x10 => Key::Up,
+            0x0e => Key::Down,
+            b if b >= 0x20 => Key::Char(b),
+            _ => continue,
+        };
+        ret.push(key);
+    }
+    return ret;
+}
@@ -0,0 +1,122 @@
+use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
+use std::io::Write;
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+
+use crate::output::MetadataField;
+use crate::Config;
+
+const SNAPSHOT_HEADER: &[u8] = b"pff-snapshot 1\n";
+
+// SnapshotEntry, a result and (optionally) its size and mtime (in nanoseconds), used to detect changed entries
+pub struct SnapshotEntry {
+    pub path: PathBuf,
+    pub category: usize,
+    pub size: Option<u64>,
+    pub mtime_ns: Option<i64>,
+}
+
+// Snapshot, a result set sorted (ascending) by path bytes. It's stored as a header line, then a NUL terminated record
+// per entry: '<category> <size|-> <mtime|-> <path>'
+pub struct Snapshot {
+    pub entries: Vec<SnapshotEntry>,
+}
+
+impl Snapshot {
+    // from_results, sizes and mtimes are only recorded when they're requested with `--metadata`
+    pub fn from_results(cfg: &Config, sorted_asc_results: &[(PathBuf, usize)]) -> Snapshot {
+        let with_size = cfg.metadata_fields.contains(&MetadataField::Size);
+        let with_mtime = cfg.metadata_fields.contains(&MetadataField::Mtime);
+        let entries = sorted_asc_results.par_iter().map(|(path, category)| {
+            let mut ret = SnapshotEntry { path: path.clone(), category: *category, size: None, mtime_ns: None };
+            if with_size || with_mtime {
+                if let Ok(md) = std::fs::symlink_metadata(path) {
+                    ret.size = with_size.then_some(md.size());
+                    ret.mtime_ns = with_mtime.then_some(md.mtime() * 1_000_000_000 + md.mtime_nsec());
+                }
+            }
+            return ret;
+        }).collect();
+        return Snapshot { entries };
+    }
+
+    pub fn read(path: &Path) -> std::io::Result<Snapshot> {
+        let bytes = std::fs::read(path)?;
+        let Some(records) = bytes.strip_prefix(SNAPSHOT_HEADER) else {
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, format!("'{}' isn't a `pff` snapshot", path.display())));
+        };
+
+        let mut entries = Vec::new();
+        for record in records.split(|b| *b == 0).filter(|r| r.len() > 0) {
+            let mut fields = record.splitn(4, |b| *b == b' ');
+            let (Some(category), Some(size), Some(mtime), Some(entry_path)) = (fields.next(), fields.next(), fields.next(), fields.next()) else {
+                return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, format!("invalid record in snapshot '{}'", path.display())));
+            };
+            let Some(category) = parse_field::<usize>(category).flatten() else {
+                return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, format!("invalid record in snapshot '{}'", path.display())));
+            };
+            let (Some(size), Some(mtime_ns)) = (parse_field::<u64>(size), parse_field::<i64>(mtime)) else {
+                return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, format!("invalid record in snapshot '{}'", path.display())));
+            };
+            entries.push(SnapshotEntry { path: PathBuf::from(std::ffi::OsString::from_vec(entry_path.to_vec())), category, size, mtime_ns });
+        }
+        return Ok(Snapshot { entries });
+    }
+
+    pub fn write(&self, path: &Path) -> std::io::Result<()> {
+        let mut w = std::io::BufWriter::new(std::fs::File::create(path)?);
+        w.write_all(SNAPSHOT_HEADER)?;
+        for entry in &self.entries {
+            let size = entry.size.map_or(String::from("-"), |s| s.to_string());
+            let mtime = entry.mtime_ns.map_or(String::from("-"), |m| m.to_string());
+            w.write_all(format!("{} {} {} ", entry.category, size, mtime).as_bytes())?;
+            w.write_all(entry.path.as_os_str().as_bytes())?;
+            w.write_all(&[0])?;
+        }
+        return w.flush();
+    }
+
+    // diff, the entries that were added ('+'), removed ('-') or changed ('~') since `old`. An entry has changed if its
+    // kind did, or its size or mtime did when they're in both snapshots
+    pub fn diff<'a>(&'a self, old: &'a Snapshot) -> Vec<(u8, &'a SnapshotEntry)> {
+        let mut ret = Vec::new();
+        let (mut i, mut j) = (0, 0);
+        while i < self.entries.len() || j < old.entries.len() {
+            let ord = match (self.entries.get(i), old.entries.get(j)) {
+                (Some(new), Some(prev)) => new.path.as_os_str().as_bytes().cmp(prev.path.as_os_str().as_bytes()),
+                (Some(_), None) => std::cmp::Ordering::Less,
+                _ => std::cmp::Ordering::Greater,
+            };
+            match ord {
+                std::cmp::Ordering::Less => {
+                    ret.push((b'+', &self.entries[i]));
+                    i += 1;
+                }
+                std::cmp::Ordering::Greater => {
+                    ret.push((b'-', &old.entries[j]));
+                    j += 1;
+                }
+                std::cmp::Ordering::Equal => {
+                    let (new, prev) = (&self.entries[i], &old.entries[j]);
+                    let is_size_changed = new.size.is_some() && prev.size.is_some() && new.size != prev.size;
+                    let is_mtime_changed = new.mtime_ns.is_some() && prev.mtime_ns.is_some() && new.mtime_ns != prev.mtime_ns;
+                    if new.category != prev.category || is_size_changed || is_mtime_changed {
+                        ret.push((b'~', new));
+                    }
+                    i += 1;
+                    j += 1;
+                }
+            }
+        }
+        return ret;
+    }
+}
+
+// parse_field, '-' is a field that wasn't recorded, anything unparseable is `None`
+fn parse_field<T: std::str::FromStr>(field: &[u8]) -> Option<Option<T>> {
+    if field == b"-" {
+        return Some(None);
+    }
+    return std::str::from_utf8(field).ok()?.parse().ok().map(Some);
+}
@@ -0,0 +1,83 @@
+use std::collections::BTreeMap;
+use std::time::Instant;
+
+use crate::matches;
+use crate::walk::WalkStats;
+
+const CATEGORY_NAMES: [&str; matches::NUM_FILE_CATEGORIES] = ["file", "symlink", "dir", "hidden file", "hidden symlink", "hidden dir"];
+
+// RunStats, totals for a whole `find` that are written to stderr with `--stats`, to help tune `-t` and `-fdl`
+pub struct RunStats {
+    start: Instant,
+    rounds: usize,
+    matches: usize,
+    totals: WalkStats,
+    per_thread: BTreeMap<Option<usize>, (usize, WalkStats)>,
+}
+
+impl RunStats {
+    pub fn new() -> RunStats {
+        return RunStats { start: Instant::now(), rounds: 0, matches: 0, totals: WalkStats::default(), per_thread: BTreeMap::new() };
+    }
+
+    pub fn add_round(&mut self) {
+        self.rounds += 1;
+    }
+
+    pub fn add_matches(&mut self, num_matches: usize) {
+        self.matches += num_matches;
+    }
+
+    // add_walk, `thread_idx` is the rayon thread that did the walk, `None` is the main thread
+    pub fn add_walk(&mut self, thread_idx: Option<usize>, stats: &WalkStats) {
+        self.totals.add(stats);
+        let thread_stats = self.per_thread.entry(thread_idx).or_insert((0, WalkStats::default()));
+        thread_stats.0 += 1;
+        thread_stats.1.add(stats);
+    }
+
+    // set_matches, for results that are only narrowed down once the walk is finished (e.g. `--fuzzy-path`)
+    pub fn set_matches(&mut self, num_matches: usize) {
+        self.matches = num_matches;
+    }
+
+    pub fn num_matches(&self) -> usize {
+        return self.matches;
+    }
+
+    pub fn num_errors(&self) -> usize {
+        return self.totals.errors;
+    }
+
+    pub fn print(&self) {
+        let mut lines = vec![
+            format!("directories read : {}", self.totals.dirs_read),
+            format!("entries examined : {}", self.totals.entries_examined),
+            format!("matches          : {}", self.matches),
+            format!("errors           : {}", self.totals.errors),
+            format!("rounds           : {}", self.rounds),
+            format!("wall time        : {:.3}ms", self.start.elapsed().as_secs_f64() * 1000.0),
+            String::from("per thread (walks / directories read / entries examined):"),
+        ];
+        for (thread_idx, (num_walks, stats)) in &self.per_thread {
+            let name = match thread_idx {
+                Some(idx) => format!("thread {}", idx),
+                None => String::from("main"),
+            };
+            lines.push(format!("  {:<10} {} / {} / {}", name, num_walks, stats.dirs_read, stats.entries_examined));
+        }
+        eprintln!("{}", lines.join("\n"));
+    }
+}
+
+// count_lines, the `--count` output, one line per (filtered) category and a total
+pub fn count_lines(categories: &[usize], match_counts: &[usize; matches::NUM_FILE_CATEGORIES]) -> Vec<Vec<u8>> {
+    let mut ret = Vec::with_capacity(categories.len() + 1);
+    let mut total = 0;
+    for idx in categories {
+        ret.push(format!("{:<15} {}", CATEGORY_NAMES[*idx], match_counts[*idx]).into_bytes());
+        total += match_counts[*idx];
+    }
+    ret.push(format!("{:<15} {}", "total", total).into_bytes());
+    return ret;
+}
@@ -0,0 +1,165 @@
+use std::collections::HashMap;
+use std::io::Write;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::MetadataExt;
+use std::path::Path;
+
+use crate::label;
+use crate::matches;
+
+const PASSWD_PATH: &str = "/etc/passwd";
+
+pub const PLACEHOLDER_HELP: &str = "%p path, %P path relative to root, %f basename, %h parent dir,
+                                            %x extension, %n stem, %s size, %t mtime (seconds since epoch),
+                                            %m mode (octal), %u owner, %d depth, %i inode, %l symlink target,
+                                            %y label, %% a literal '%'. Escapes: \\n, \\t, \\0 and \\\\";
+
+#[derive(PartialEq)]
+enum Token {
+    Literal(Vec<u8>),
+    Path,
+    RelativePath,
+    BaseName,
+    ParentDir,
+    Extension,
+    Stem,
+    Size,
+    Mtime,
+    Mode,
+    Owner,
+    Depth,
+    Inode,
+    SymlinkTarget,
+    Label,
+}
+
+// Template, a `--printf` format string compiled once into tokens, so each entry only needs to be rendered
+pub struct Template {
+    tokens: Vec<Token>,
+    needs_metadata: bool,
+    owner_names: HashMap<u32, String>,
+}
+
+impl Template {
+    pub fn compile(fmt: &str) -> Result<Template, String> {
+        let mut tokens: Vec<Token> = Vec::new();
+        let mut literal: Vec<u8> = Vec::new();
+        let mut chars = fmt.chars();
+        while let Some(c) = chars.next() {
+            if c == '\\' {
+                match chars.next() {
+                    Some('n') => literal.push(b'\n'),
+                    Some('t') => literal.push(b'\t'),
+                    Some('0') => literal.push(b'\0'),
+                    Some('\\') => literal.push(b'\\'),
+                    Some(other) => return Err(format!("invalid escape '\\{}' in `--printf` format", other)),
+                    None => return Err(String::from("`--printf` format can't end with a '\\'")),
+                }
+                continue;
+            } else if c != '%' {
+                let mut buf = [0; 4];
+                literal.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+                continue;
+            }
+
+            let placeholder = match chars.next() {
+                Some('%') => {
+                    literal.push(b'%');
+                    continue;
+                }
+                Some('p') => Token::Path,
+                Some('P') => Token::RelativePath,
+                Some('f') => Token::BaseName,
+                Some('h') => Token::ParentDir,
+                Some('x') => Token::Extension,
+                Some('n') => Token::Stem,
+                Some('s') => Token::Size,
+                Some('t') => Token::Mtime,
+                Some('m') => Token::Mode,
+                Some('u') => Token::Owner,
+                Some('d') => Token::Depth,
+                Some('i') => Token::Inode,
+                Some('l') => Token::SymlinkTarget,
+                Some('y') => Token::Label,
+                Some(other) => return Err(format!("invalid placeholder '%{}' in `--printf` format", other)),
+                None => return Err(String::from("`--printf` format can't end with a '%'")),
+            };
+            if literal.len() > 0 {
+                tokens.push(Token::Literal(std::mem::take(&mut literal)));
+            }
+            tokens.push(placeholder);
+        }
+        if literal.len() > 0 {
+            tokens.push(Token::Literal(literal));
+        }
+
+        let needs_metadata = tokens.iter().any(|t| matches!(t, Token::Size | Token::Mtime | Token::Mode | Token::Owner | Token::Inode));
+        let mut owner_names = HashMap::new();
+        if tokens.contains(&Token::Owner) {
+            owner_names = read_owner_names();
+        }
+        return Ok(Template { tokens, needs_metadata, owner_names });
+    }
+
+    // render, `path` is the walked path (used for metadata) and `out_path` is the same path in the output `PathMode`
+    pub fn render(&self, path: &Path, out_path: &Path, category: usize, root: &Path, depth: usize) -> Vec<u8> {
+        let (is_hidden, is_file, is_symlink) = matches::category_properties(category);
+        let mut md = None;
+        if self.needs_metadata {
+            md = std::fs::symlink_metadata(path).ok();
+        }
+
+        let mut ret: Vec<u8> = Vec::new();
+        for t in &self.tokens {
+            match t {
+                Token::Literal(l) => ret.extend_from_slice(l),
+                Token::Path => ret.extend_from_slice(out_path.as_os_str().as_bytes()),
+                Token::RelativePath => {
+                    let rel = path.strip_prefix(root).unwrap_or(path);
+                    ret.extend_from_slice(rel.as_os_str().as_bytes());
+                }
+                Token::BaseName => ret.extend_from_slice(path.file_name().unwrap_or_default().as_bytes()),
+                Token::ParentDir => ret.extend_from_slice(out_path.parent().unwrap_or(Path::new("")).as_os_str().as_bytes()),
+                Token::Extension => ret.extend_from_slice(path.extension().unwrap_or_default().as_bytes()),
+                Token::Stem => ret.extend_from_slice(path.file_stem().unwrap_or_default().as_bytes()),
+                Token::Size => { let _ = write!(ret, "{}", md.as_ref().map_or(0, |m| m.size())); }
+                Token::Mtime => { let _ = write!(ret, "{}", md.as_ref().map_or(0, |m| m.mtime())); }
+                Token::Mode => { let _ = write!(ret, "{:o}", md.as_ref().map_or(0, |m| m.mode() & 0o7777)); }
+                Token::Owner => {
+                    let uid = md.as_ref().map_or(0, |m| m.uid());
+                    match self.owner_names.get(&uid) {
+                        Some(name) => ret.extend_from_slice(name.as_bytes()),
+                        None => { let _ = write!(ret, "{}", uid); }
+                    }
+                }
+                Token::Depth => { let _ = write!(ret, "{}", depth); }
+                Token::Inode => { let _ = write!(ret, "{}", md.as_ref().map_or(0, |m| m.ino())); }
+                Token::SymlinkTarget => {
+                    if is_symlink {
+                        if let Ok(target) = std::fs::read_link(path) {
+                            ret.extend_from_slice(target.as_os_str().as_bytes());
+                        }
+                    }
+                }
+                Token::Label => ret.extend_from_slice(label::generate_label(is_hidden, is_file, is_symlink).as_bytes()),
+            }
+        }
+        return ret;
+    }
+}
+
+// read_owner_names, maps uids to user names from `/etc/passwd`, owners without an entry are output as their uid
+fn read_owner_names() -> HashMap<u32, String> {
+    let mut ret = HashMap::new();
+    let Ok(passwd) = std::fs::read_to_string(PASSWD_PATH) else { return ret };
+    for line in passwd.lines() {
+        let fields: Vec<&str> = line.split(':').collect();
+        if fields.len() < 3 {
+            continue;
+        }
+        if let Ok(uid) = fields[2].parse::<u32>() {
+            ret.entry(uid).or_insert(fields[0].to_string());
+        }
+    }
+    return ret;
+}
@@ -0,0 +1,79 @@
+use std::ffi::OsString;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+
+use crate::matches;
+use crate::output::Renderer;
+
+// TreeNode, an entry in the tree, nodes without a category are the ancestors needed to reach a match
+struct TreeNode {
+    name: OsString,
+    category: Option<usize>,
+    children: Vec<TreeNode>,
+}
+
+impl TreeNode {
+    fn num_matches(&self) -> usize {
+        return self.children.iter().map(|c| c.category.is_some() as usize + c.num_matches()).sum();
+    }
+}
+
+// render_tree, renders the sorted results as an indented tree (like `tree -P`), including just the ancestor directories
+// needed to reach them. Entries deeper than `max_depth` are collapsed into a count on their ancestor
+pub fn render_tree(sorted_results: Vec<(PathBuf, usize)>, root: &Path, renderer: &Renderer, max_depth: Option<usize>) -> Vec<Vec<u8>> {
+    let mut tree_root = TreeNode { name: OsString::new(), category: None, children: Vec::new() };
+    for (path, category) in sorted_results {
+        let Ok(rest) = path.strip_prefix(root) else { continue };
+
+        // Results are sorted by their bytes, so every entry under a directory is contiguous and only the last child of
+        // each node needs to be checked when inserting
+        let mut node = &mut tree_root;
+        for comp in rest.components() {
+            let name = comp.as_os_str();
+            let is_last_child = node.children.last().is_some_and(|c| c.name == name);
+            if !is_last_child {
+                node.children.push(TreeNode { name: name.to_os_string(), category: None, children: Vec::new() });
+            }
+            node = node.children.last_mut().unwrap();
+        }
+        node.category = Some(category);
+    }
+
+    let mut lines = vec![renderer.output_path(root).as_os_str().as_bytes().to_vec()];
+    let mut prefix: Vec<u8> = Vec::new();
+    push_tree_lines(&mut lines, &tree_root, &mut prefix, 1, renderer, max_depth);
+    return lines;
+}
+
+fn push_tree_lines(lines: &mut Vec<Vec<u8>>, node: &TreeNode, prefix: &mut Vec<u8>, depth: usize, renderer: &Renderer, max_depth: Option<usize>) {
+    for (i, child) in node.children.iter().enumerate() {
+        let is_last = i == node.children.len() - 1;
+        let mut line = prefix.clone();
+        line.extend_from_slice(if is_last { "└── ".as_bytes() } else { "├── ".as_bytes() });
+
+        // Ancestors are always directories, they're only highlighted when they were matched too
+        let (is_file, is_symlink) = match child.category {
+            Some(category) => { let props = matches::category_properties(category); (props.1, props.2) }
+            None => (false, false),
+        };
+        line.extend_from_slice(&renderer.render_tree_name(&child.name, child.category.is_some(), is_file, is_symlink));
+        if !is_file {
+            line.push(b'/');
+        }
+
+        let is_collapsed = max_depth.is_some_and(|d| depth >= d) && child.children.len() > 0;
+        if is_collapsed {
+            let num_matches = child.num_matches();
+            line.extend_from_slice(format!(" [{} {}]", num_matches, if num_matches == 1 { "match" } else { "matches" }).as_bytes());
+        }
+        lines.push(line);
+        if is_collapsed {
+            continue;
+        }
+
+        let prev_len = prefix.len();
+        prefix.extend_from_slice(if is_last { "    ".as_bytes() } else { "│   ".as_bytes() });
+        push_tree_lines(lines, child, prefix, depth + 1, renderer, max_depth);
+        prefix.truncate(prev_len);
+    }
+}
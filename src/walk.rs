@@ -1,74 +1,405 @@
-use regex::bytes::Regex;
-use std::ffi::OsStr;
+use regex::bytes::{Regex, RegexSet};
+use std::ffi::{OsStr, OsString};
 use std::os::unix::ffi::OsStrExt;
-use std::path::PathBuf;
+use std::os::unix::fs::MetadataExt;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
-use crate::label;
+use crate::expr::EvalCtx;
+use crate::find;
 use crate::matches;
+use crate::mounts::DeviceFilter;
+use crate::Config;
 
 const HIDDEN_RX_STR: &str = r".*\/\..*";
 
-pub fn walk_collect_matches_until_limit(initial_dirs: &mut Vec<std::path::PathBuf>, limit: usize, label_pos: i8, match_rx: Regex, match_exact: Option<&String>) -> std::io::Result<(Vec<PathBuf>, [Vec<String>; matches::NUM_FILE_CATEGORIES])> {
-    let mut dir_q: Vec<PathBuf> = std::mem::take(initial_dirs);
-    let mut match_exact_basename = Some(OsStr::new(""));
-    if match_exact.is_some() {
-        match_exact_basename = Some(OsStr::new(match_exact.unwrap()));
+// WalkedDir, records whether a directory that was read contains anything other than directories, used to work out which
+// directories can be pruned (`--prune-empty-report`) and which directories to watch (`--watch`). When building an index,
+// its times and the name and category of each of its entries are kept too
+pub struct WalkedDir {
+    pub path: PathBuf,
+    pub has_non_dir_entries: bool,
+    pub times: DirTimes,
+    pub entries: Vec<(OsString, usize)>,
+}
+
+// DirTimes, a directory's mtime and ctime (in nanoseconds), adding, removing or renaming an entry updates both
+#[derive(Default, Clone, Copy, PartialEq)]
+pub struct DirTimes {
+    pub mtime_ns: i64,
+    pub ctime_ns: i64,
+}
+
+impl DirTimes {
+    pub fn from_metadata(md: &std::fs::Metadata) -> DirTimes {
+        return DirTimes { mtime_ns: md.mtime() * 1_000_000_000 + md.mtime_nsec(), ctime_ns: md.ctime() * 1_000_000_000 + md.ctime_nsec() };
+    }
+}
+
+#[derive(Default, Clone, Copy)]
+pub struct WalkStats {
+    pub dirs_read: usize,
+    pub entries_examined: usize,
+    pub errors: usize,
+}
+
+impl WalkStats {
+    pub fn add(&mut self, other: &WalkStats) {
+        self.dirs_read += other.dirs_read;
+        self.entries_examined += other.entries_examined;
+        self.errors += other.errors;
     }
-    
+}
+
+pub struct WalkResult {
+    pub paths_to_distribute: Vec<PathBuf>,
+    pub matches: [Vec<PathBuf>; matches::NUM_FILE_CATEGORIES],
+    pub match_counts: [usize; matches::NUM_FILE_CATEGORIES],
+    pub walked_dirs: Vec<WalkedDir>,
+    pub stats: WalkStats,
+}
+
+// WalkCtx, everything a walk needs that's the same for every thread and round. With several patterns (`-e`) they're
+// matched with `match_set`, `match_rx` is then their alternation, which is only used to highlight matches
+pub struct WalkCtx<'a> {
+    pub cfg: &'a Config,
+    pub match_rx: Regex,
+    pub match_exact: Option<String>,
+    pub match_set: Option<RegexSet>,
+    pub match_any: bool,
+    pub dev_filter: DeviceFilter,
+    // With `--max-results` (unsorted), the matches found so far in the output categories, and how many of them have been
+    // claimed for output. The walk stops once enough are found, or once the results are no longer wanted (`--pick`)
+    counted_categories: Vec<usize>,
+    num_found: AtomicUsize,
+    num_claimed: AtomicUsize,
+    is_stopped: Arc<AtomicBool>,
+    // With `--timeout`, no more directories are read once it's passed
+    deadline: Option<Instant>,
+    // With `--contains-count`, the number of content matches of each matched file, counted when it's matched so it's
+    // only read once. They're taken by the `Renderer` when the file is output
+    pub content_counts: Arc<Mutex<HashMap<PathBuf, usize>>>,
+}
+
+impl<'a> WalkCtx<'a> {
+    // new, compiles the regex OR exact match for the pattern(s), based on config. An empty pattern matches everything, so
+    // it's never compiled (e.g. `pff -x rs '' .` only checks extensions)
+    pub fn new(cfg: &'a Config, target: &str, dev_filter: DeviceFilter) -> std::io::Result<WalkCtx<'a>> {
+        let match_any = target.len() == 0 && cfg.patterns.len() == 0 && !cfg.equality_match;
+        let mut ret = WalkCtx {
+            cfg,
+            match_rx: Regex::new("").unwrap(),
+            match_exact: None,
+            match_set: None,
+            match_any,
+            dev_filter,
+            counted_categories: find::filtered_categories(cfg),
+            num_found: AtomicUsize::new(0),
+            num_claimed: AtomicUsize::new(0),
+            is_stopped: Arc::new(AtomicBool::new(false)),
+            deadline: cfg.timeout.map(|timeout| Instant::now() + timeout),
+            content_counts: Arc::new(Mutex::new(HashMap::new())),
+        };
+        if cfg.patterns.len() > 0 {
+            // Exact names are matched as anchored, escaped regexes, so they can share the set
+            let patterns: Vec<String> = cfg.patterns.iter().map(|p| if cfg.equality_match { format!("^{}$", regex::escape(p)) } else { p.clone() }).collect();
+            let (Ok(match_set), Ok(match_rx)) = (RegexSet::new(&patterns), Regex::new(&patterns.iter().map(|p| format!("(?:{})", p)).collect::<Vec<String>>().join("|"))) else {
+                return Err(std::io::Error::new(std::io::ErrorKind::Other, "Failed to compile regex"));
+            };
+            ret.match_set = Some(match_set);
+            ret.match_rx = match_rx;
+        } else if cfg.equality_match {
+            ret.match_exact = Some(target.to_string());
+        } else {
+            let Ok(match_rx) = Regex::new(target) else {
+                return Err(std::io::Error::new(std::io::ErrorKind::Other, "Failed to compile regex"));
+            };
+            ret.match_rx = match_rx;
+        }
+        return Ok(ret);
+    }
+
+    // with_stop_flag, shares the flag that stops the walk, so whatever consumes the results can stop it
+    pub fn with_stop_flag(mut self, is_stopped: Arc<AtomicBool>) -> Self {
+        self.is_stopped = is_stopped;
+        return self;
+    }
+
+    // is_match, checks a basename against the `-x`/`--type` filter (a cheap suffix check) then the pattern(s), each
+    // basename is only scanned once. With `--invert` it's the entries that don't match the pattern(s)
+    pub fn is_match(&self, base_name: &OsStr) -> bool {
+        if let Some(name_filter) = &self.cfg.name_filter {
+            if !name_filter.is_match(base_name.as_bytes()) {
+                return false;
+            }
+        }
+        // Full paths are scored once the results are collected, as a subsequence can span directories
+        let is_pattern_match = if let Some(fuzzy) = &self.cfg.fuzzy {
+            self.cfg.fuzzy_full_path || fuzzy.is_match(base_name.as_bytes())
+        } else if self.match_any {
+            true
+        } else if let Some(exact) = &self.match_exact {
+            base_name == OsStr::new(exact)
+        } else if let Some(match_set) = &self.match_set {
+            match_set.is_match(base_name.as_bytes())
+        } else {
+            self.match_rx.is_match(base_name.as_bytes())
+        };
+        return is_pattern_match != self.cfg.invert;
+    }
+
+    // record_found, counts a match towards `--max-results`. Sorted results need every match, so they never stop the walk
+    pub fn record_found(&self, category: usize) {
+        let Some(max_results) = self.cfg.max_results else { return };
+        if self.cfg.is_sorted || !self.counted_categories.contains(&category) {
+            return;
+        }
+        if self.num_found.fetch_add(1, Ordering::Relaxed) + 1 >= max_results {
+            self.is_stopped.store(true, Ordering::Relaxed);
+        }
+    }
+
+    // claim_results, how many of `num_results` (unsorted) results can be output, so no more than `--max-results` are
+    pub fn claim_results(&self, num_results: usize) -> usize {
+        let (Some(max_results), false) = (self.cfg.max_results, self.cfg.is_sorted) else {
+            return num_results;
+        };
+        let num_claimed = self.num_claimed.fetch_add(num_results, Ordering::Relaxed);
+        return max_results.saturating_sub(num_claimed).min(num_results);
+    }
+
+    // is_stopped, whether enough results have been found (or they're no longer wanted), so no more directories need to
+    // be read
+    pub fn is_stopped(&self) -> bool {
+        return self.is_stopped.load(Ordering::Relaxed);
+    }
+
+    // stop, no more directories are read, e.g. once the walk has been abandoned (`--timeout`)
+    pub fn stop(&self) {
+        self.is_stopped.store(true, Ordering::Relaxed);
+    }
+
+    // is_timed_out, a directory that's already being read is still finished, as reads can't be interrupted
+    pub fn is_timed_out(&self) -> bool {
+        return self.deadline.is_some_and(|deadline| Instant::now() >= deadline);
+    }
+
+    // is_content_match, with `--contains` files (only) are matched by their contents too, after their name. Only the
+    // first match is needed, unless they're counted (`--contains-count`)
+    pub fn is_content_match(&self, path: &Path) -> bool {
+        let Some(contains) = &self.cfg.contains else {
+            return true;
+        };
+        if !self.cfg.contains_count {
+            return contains.num_matches(path, false) > 0;
+        }
+        let num_matches = contains.num_matches(path, true);
+        if num_matches > 0 {
+            self.content_counts.lock().unwrap().insert(path.to_path_buf(), num_matches);
+        }
+        return num_matches > 0;
+    }
+}
+
+// walk_collect_matches_until_limit, walks `initial_dirs` (breadth first) until `limit` files + dirs have been read,
+// returning the matches and the directories that are yet to be walked. On the `is_root_walk` the first directory is the
+// root, it's only matched with `--include-target` and failing to read it is an error
+pub fn walk_collect_matches_until_limit(initial_dirs: &mut Vec<std::path::PathBuf>, limit: usize, ctx: &WalkCtx, is_root_walk: bool) -> std::io::Result<WalkResult> {
+    let cfg = ctx.cfg;
+    let mut dir_q: Vec<PathBuf> = std::mem::take(initial_dirs);
+
     // Actual limit should be min(limit, some.len())
     let mut fd_limit = limit;
     if limit < dir_q.len() {
         fd_limit = dir_q.len();
     }
-    let mut matches: [Vec<String>; matches::NUM_FILE_CATEGORIES] = matches::initialise_matches_capacities(fd_limit);
-    
+    // Only counts are kept with `--count`, so no paths are allocated for matches
+    let mut matches: [Vec<PathBuf>; matches::NUM_FILE_CATEGORIES] = [const { Vec::new() }; matches::NUM_FILE_CATEGORIES];
+    if !cfg.count_only {
+        matches = matches::initialise_matches_capacities(fd_limit);
+    }
+    let mut match_counts = [0; matches::NUM_FILE_CATEGORIES];
+    let mut walked_dirs: Vec<WalkedDir> = Vec::new();
+    let mut errors = 0;
+
     let mut f_idx = 0;
     let mut d_idx = 0;
-    let hidden_rx = Regex::new(&HIDDEN_RX_STR).unwrap();
-    let is_match_exact = match_exact.is_some();
-    while (f_idx + d_idx) < fd_limit && d_idx < dir_q.len() {
+    let hidden_rx = Regex::new(HIDDEN_RX_STR).unwrap();
+    while (f_idx + d_idx) < fd_limit && d_idx < dir_q.len() && !ctx.is_stopped() && !ctx.is_timed_out() {
         let dir_base_name = dir_q[d_idx].file_name();
-        let dir_path = dir_q[d_idx].to_path_buf().into_os_string();
-        let dir_hidden = hidden_rx.is_match(dir_path.as_bytes());
-        let is_match: bool = (is_match_exact && dir_base_name == match_exact_basename) || 
-                             (!is_match_exact && match_rx.is_match(dir_base_name.unwrap().as_bytes()));
-        if is_match {
-            let dir_path_string = format!("{}/", dir_path.into_string().unwrap());
-            matches::insert_entry_in_matches(&mut matches, dir_path_string, dir_hidden, false, false);
+        let dir_hidden = hidden_rx.is_match(dir_q[d_idx].as_os_str().as_bytes());
+        let is_root = is_root_walk && d_idx == 0;
+        let mut is_match: bool = (!is_root || cfg.include_target_in_output) && ctx.is_match(dir_base_name.unwrap_or_default());
+
+        // With an expression, directories are matched when they're found (so `-prune` stops them being queued), apart
+        // from the root which is matched here
+        if let Some(expr) = &cfg.expr {
+            is_match = false;
+            if is_root && cfg.include_target_in_output {
+                let md = std::fs::symlink_metadata(&dir_q[0])?;
+                let eval = expr.eval(&EvalCtx::new(&dir_q[0], dir_base_name.unwrap_or_default(), md.file_type()));
+                is_match = eval.is_match;
+                if eval.is_pruned {
+                    if is_match {
+                        record_match(ctx, &mut matches, &mut match_counts, matches::category_index(dir_hidden, false, false), || dir_match_path(&dir_q[0]));
+                    }
+                    d_idx += 1;
+                    continue;
+                }
+            }
         }
-        
-        let dir_entries = std::fs::read_dir(&dir_q[d_idx])?;
+
+        // An index records the times from before the directory is read, so changes made while reading it aren't missed
+        let mut dir_times = DirTimes::default();
+        if cfg.index_build {
+            if let Ok(md) = std::fs::symlink_metadata(&dir_q[d_idx]) {
+                dir_times = DirTimes::from_metadata(&md);
+            }
+        }
+
+        // Unreadable directories are skipped (and counted), unless it's the root. Their contents are unknown, so they (and
+        // their ancestors) can't be reported as prunable
+        let dir_entries = match std::fs::read_dir(&dir_q[d_idx]) {
+            Ok(entries) => entries,
+            Err(e) => {
+                if is_root {
+                    return Err(e);
+                }
+                errors += 1;
+                if cfg.prune_empty_report {
+                    walked_dirs.push(WalkedDir { path: dir_q[d_idx].clone(), has_non_dir_entries: true, times: dir_times, entries: Vec::new() });
+                }
+                d_idx += 1;
+                continue;
+            }
+        };
         d_idx += 1;
+        let mut num_dir_entries = 0;
+        let mut has_non_dir_entries = false;
+        let mut indexed_entries: Vec<(OsString, usize)> = Vec::new();
         for ent in dir_entries {
-            let Ok(val) = ent else { continue };
-            let Ok(ft) = val.file_type() else { continue };
+            // An entry that can't be read might not be a directory
+            let Ok(val) = ent else { errors += 1; has_non_dir_entries = true; continue };
+            let Ok(ft) = val.file_type() else { errors += 1; has_non_dir_entries = true; continue };
             f_idx += 1;
-            
+            num_dir_entries += 1;
+
+            if cfg.index_build {
+                indexed_entries.push((val.file_name(), entry_category(dir_hidden, &val.file_name(), &ft)));
+            }
+
+            if let Some(expr) = &cfg.expr {
+                let ent_path = val.path();
+                let ent_name = val.file_name();
+                let eval = expr.eval(&EvalCtx::new(&ent_path, &ent_name, ft));
+                let is_dir = ft.is_dir();
+                if eval.is_match && (is_dir || ctx.is_content_match(&ent_path)) {
+                    let idx = matches::category_index(dir_hidden || ent_name.as_bytes().starts_with(b"."), !is_dir, ft.is_symlink());
+                    record_match(ctx, &mut matches, &mut match_counts, idx, || if is_dir { dir_match_path(&ent_path) } else { ent_path.clone() });
+                }
+                if !is_dir || (ctx.dev_filter.is_active() && is_on_excluded_device(&val, &ctx.dev_filter)) {
+                    has_non_dir_entries = true;
+                } else if !eval.is_pruned {
+                    dir_q.push(ent_path);
+                }
+                continue;
+            }
+
             if ft.is_file() || ft.is_symlink() {
+                has_non_dir_entries = true;
                 let file_base_name = val.file_name();
-                let is_match: bool = (is_match_exact && file_base_name == match_exact_basename.unwrap()) || 
-                                     (!is_match_exact && match_rx.is_match(file_base_name.as_bytes()));
-                if is_match {
-                    let file_path_string = val.path().into_os_string().into_string().unwrap();
-                    matches::insert_entry_in_matches(&mut matches, file_path_string, dir_hidden || file_base_name.as_bytes().starts_with(&['.' as u8]), true, ft.is_symlink());
+                if ctx.is_match(&file_base_name) && (!cfg.match_empty || is_empty_file(&val, &ft)) && ctx.is_content_match(&val.path()) {
+                    let idx = matches::category_index(dir_hidden || file_base_name.as_bytes().starts_with(b"."), true, ft.is_symlink());
+                    record_match(ctx, &mut matches, &mut match_counts, idx, || val.path());
+                }
+                continue;
+            }
+
+            // Directories on excluded filesystems are still matched, but not descended into (same as `find -xdev`)
+            if ctx.dev_filter.is_active() && is_on_excluded_device(&val, &ctx.dev_filter) {
+                has_non_dir_entries = true;
+                let mount_base_name = val.file_name();
+                if ctx.is_match(&mount_base_name) && !cfg.match_empty {
+                    let idx = matches::category_index(dir_hidden || mount_base_name.as_bytes().starts_with(b"."), false, false);
+                    record_match(ctx, &mut matches, &mut match_counts, idx, || dir_match_path(&val.path()));
                 }
                 continue;
             }
 
             dir_q.push(val.path());
         }
-    }
 
-    let is_labelled = label_pos != 0;
-    if is_labelled {
-        for i in 0..matches.len() {
-            let is_hidden = i >= 3;
-            let file_type_idx = i % 3;
-            for j in 0..matches[i].len() {
-                matches[i][j] = label::add_label(&mut matches[i][j], label_pos, is_hidden, file_type_idx == 0, file_type_idx == 1);
-            }
+        // Directories are matched after being read, so that their emptiness is known without another syscall
+        if is_match && (!cfg.match_empty || num_dir_entries == 0) {
+            let idx = matches::category_index(dir_hidden, false, false);
+            record_match(ctx, &mut matches, &mut match_counts, idx, || dir_match_path(&dir_q[d_idx - 1]));
+        }
+        if cfg.prune_empty_report || cfg.index_build || cfg.watch {
+            walked_dirs.push(WalkedDir { path: dir_q[d_idx - 1].clone(), has_non_dir_entries, times: dir_times, entries: indexed_entries });
         }
     }
 
-    return Ok((dir_q.drain(d_idx..).collect(), matches));
-}
\ No newline at end of file
+    let stats = WalkStats { dirs_read: d_idx, entries_examined: f_idx, errors };
+    return Ok(WalkResult { paths_to_distribute: dir_q.drain(d_idx..).collect(), matches, match_counts, walked_dirs, stats });
+}
+
+// record_match, either stores the path of a match or just counts it, `make_path` is only called when it's stored
+fn record_match<F: FnOnce() -> PathBuf>(ctx: &WalkCtx, matches: &mut [Vec<PathBuf>; matches::NUM_FILE_CATEGORIES], match_counts: &mut [usize; matches::NUM_FILE_CATEGORIES], idx: usize, make_path: F) {
+    ctx.record_found(idx);
+    if ctx.cfg.count_only {
+        match_counts[idx] += 1;
+        return;
+    }
+    matches[idx].push(make_path());
+}
+
+// entry_category, the category of an entry in a directory, it's hidden if the directory is
+pub fn entry_category(dir_hidden: bool, name: &OsStr, ft: &std::fs::FileType) -> usize {
+    let is_hidden = dir_hidden || name.as_bytes().starts_with(b".");
+    return matches::category_index(is_hidden, !ft.is_dir(), ft.is_symlink());
+}
+
+// dir_match_path, directory matches are stored with a trailing '/', so they're output (and sorted) with it
+pub fn dir_match_path(dir: &Path) -> PathBuf {
+    let mut ret = dir.as_os_str().to_os_string();
+    ret.push("/");
+    return PathBuf::from(ret);
+}
+
+// is_hidden_path, a path is hidden if any of its components start with a '.', same as `HIDDEN_RX_STR`
+pub fn is_hidden_path(path: &Path) -> bool {
+    return path.as_os_str().as_bytes().windows(2).any(|w| w == b"/.");
+}
+
+// is_empty_file, only regular files can be empty, symlinks are never considered empty (same as `find -empty`)
+fn is_empty_file(ent: &std::fs::DirEntry, ft: &std::fs::FileType) -> bool {
+    if !ft.is_file() {
+        return false;
+    }
+    let Ok(md) = ent.metadata() else { return false };
+    return md.len() == 0;
+}
+
+fn is_on_excluded_device(ent: &std::fs::DirEntry, dev_filter: &DeviceFilter) -> bool {
+    let Ok(md) = ent.metadata() else { return false };
+    return dev_filter.is_excluded(md.dev());
+}
+
+// parse_duration, a number with an optional unit: 'ms', 's' (the default), 'm' or 'h', e.g. '500ms' or '5s'
+pub fn parse_duration(s: &str) -> Option<Duration> {
+    let (num, unit_ms) = if let Some(num) = s.strip_suffix("ms") {
+        (num, 1)
+    } else if let Some(num) = s.strip_suffix('s') {
+        (num, 1000)
+    } else if let Some(num) = s.strip_suffix('m') {
+        (num, 60 * 1000)
+    } else if let Some(num) = s.strip_suffix('h') {
+        (num, 60 * 60 * 1000)
+    } else {
+        (s, 1000)
+    };
+    return num.parse::<u64>().ok()?.checked_mul(unit_ms).map(Duration::from_millis);
+}
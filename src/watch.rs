@@ -0,0 +1,166 @@
+use std::collections::HashMap;
+use std::ffi::{CString, OsStr};
+use std::io::Write;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+
+use crate::find;
+use crate::matches;
+use crate::mounts::DeviceFilter;
+use crate::output::{self, Renderer};
+use crate::walk;
+use crate::Config;
+
+const WATCH_MASK: u32 = libc::IN_CREATE | libc::IN_MOVED_TO | libc::IN_DELETE | libc::IN_MOVED_FROM | libc::IN_ONLYDIR | libc::IN_DONT_FOLLOW;
+const EVENT_BUF_LEN: usize = 64 * 1024;
+
+// Watcher, an inotify instance and the directory each of its watches is on
+struct Watcher {
+    fd: libc::c_int,
+    dirs: HashMap<libc::c_int, PathBuf>,
+}
+
+impl Watcher {
+    fn new() -> std::io::Result<Watcher> {
+        let fd = unsafe { libc::inotify_init1(libc::IN_CLOEXEC) };
+        if fd < 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        return Ok(Watcher { fd, dirs: HashMap::new() });
+    }
+
+    // add, failing to watch a directory (e.g. hitting `max_user_watches`) is reported, but doesn't stop the others
+    fn add(&mut self, dir: &Path) {
+        let Ok(c_dir) = CString::new(dir.as_os_str().as_bytes()) else { return };
+        let wd = unsafe { libc::inotify_add_watch(self.fd, c_dir.as_ptr(), WATCH_MASK) };
+        if wd < 0 {
+            eprintln!("error: failed to watch '{}': {}", dir.display(), std::io::Error::last_os_error());
+            return;
+        }
+        self.dirs.insert(wd, dir.to_path_buf());
+    }
+
+    // remove_under, a directory that's moved away keeps its watches, they're removed since its paths are no longer known
+    fn remove_under(&mut self, dir: &Path) {
+        let wds: Vec<libc::c_int> = self.dirs.iter().filter(|(_, p)| p.starts_with(dir)).map(|(wd, _)| *wd).collect();
+        for wd in wds {
+            unsafe { libc::inotify_rm_watch(self.fd, wd) };
+            self.dirs.remove(&wd);
+        }
+    }
+}
+
+// watch, runs the regular search, then watches every directory that was read and outputs matching entries as they're
+// added ('+') or removed ('-'). New directories are walked (their matches are output as added) and watched too
+pub fn watch(target: String, root: PathBuf, cfg: &Config) -> std::io::Result<()> {
+    let initial = find::find_with_walked_dirs(target.clone(), root.clone(), cfg, None)?;
+    write_lines(cfg, &initial.output);
+
+    let dev_filter = DeviceFilter::new(&root, cfg.one_file_system, &cfg.exclude_fs_types)?;
+    let walk_ctx = walk::WalkCtx::new(cfg, &target, dev_filter)?;
+    let renderer = Renderer::new(cfg, &root, &walk_ctx)?;
+    let categories = find::filtered_categories(cfg);
+
+    let mut watcher = Watcher::new()?;
+    for dir in &initial.walked_dirs {
+        watcher.add(dir);
+    }
+
+    let mut buf = vec![0u8; EVENT_BUF_LEN];
+    loop {
+        let num_read = unsafe { libc::read(watcher.fd, buf.as_mut_ptr() as *mut libc::c_void, buf.len()) };
+        if num_read < 0 {
+            let err = std::io::Error::last_os_error();
+            if err.kind() == std::io::ErrorKind::Interrupted {
+                continue;
+            }
+            return Err(err);
+        }
+
+        let mut lines: Vec<Vec<u8>> = Vec::new();
+        let mut pos = 0;
+        while pos + size_of::<libc::inotify_event>() <= num_read as usize {
+            let event = unsafe { std::ptr::read_unaligned(buf.as_ptr().add(pos) as *const libc::inotify_event) };
+            let name_start = pos + size_of::<libc::inotify_event>();
+            let name_bytes = &buf[name_start..name_start + event.len as usize];
+            let name = OsStr::from_bytes(&name_bytes[..name_bytes.iter().position(|b| *b == 0).unwrap_or(name_bytes.len())]);
+            pos = name_start + event.len as usize;
+
+            if event.mask & libc::IN_Q_OVERFLOW != 0 {
+                eprintln!("error: too many changes at once, some were missed");
+                continue;
+            }
+            if event.mask & libc::IN_IGNORED != 0 {
+                watcher.dirs.remove(&event.wd);
+                continue;
+            }
+            let Some(dir) = watcher.dirs.get(&event.wd) else { continue };
+            let path = dir.join(name);
+            let is_dir = event.mask & libc::IN_ISDIR != 0;
+            let is_added = event.mask & (libc::IN_CREATE | libc::IN_MOVED_TO) != 0;
+
+            if !is_added {
+                if is_dir && event.mask & libc::IN_MOVED_FROM != 0 {
+                    watcher.remove_under(&path);
+                }
+                // Removed entries can't be inspected, so they're never considered symlinks
+                let category = matches::category_index(walk::is_hidden_path(&path), !is_dir, false);
+                if categories.contains(&category) && walk_ctx.is_match(name) {
+                    lines.push(marked_line(b'-', &renderer, &path, category));
+                }
+                continue;
+            }
+
+            if !is_dir {
+                let Ok(md) = std::fs::symlink_metadata(&path) else { continue };
+                let category = matches::category_index(walk::is_hidden_path(&path), true, md.file_type().is_symlink());
+                if categories.contains(&category) && walk_ctx.is_match(name) {
+                    lines.push(marked_line(b'+', &renderer, &path, category));
+                }
+                continue;
+            }
+
+            // New directories are walked (including themselves) on this thread, they're usually small. A directory that's
+            // already watched was found by the walk of its (also new) parent
+            if watcher.dirs.values().any(|p| *p == path) {
+                continue;
+            }
+            let mut new_matches: Vec<(PathBuf, usize)> = Vec::new();
+            let mut new_dirs = vec![path];
+            while new_dirs.len() > 0 {
+                let Ok(new_walk) = walk::walk_collect_matches_until_limit(&mut new_dirs, cfg.file_dir_limit, &walk_ctx, false) else { break };
+                for wd in &new_walk.walked_dirs {
+                    watcher.add(&wd.path);
+                }
+                let mut categorised = new_walk.matches;
+                for idx in &categories {
+                    new_matches.extend(std::mem::take(&mut categorised[*idx]).into_iter().map(|p| (p, *idx)));
+                }
+                new_dirs = new_walk.paths_to_distribute;
+            }
+            new_matches.sort_by(|a, b| a.0.as_os_str().as_bytes().cmp(b.0.as_os_str().as_bytes()));
+            lines.extend(new_matches.iter().map(|(p, category)| marked_line(b'+', &renderer, p, *category)));
+        }
+        write_lines(cfg, &lines);
+    }
+}
+
+// marked_line, the rendered entry prefixed by its marker, e.g. '+ ./new_file'
+fn marked_line(marker: u8, renderer: &Renderer, path: &Path, category: usize) -> Vec<u8> {
+    let mut ret = vec![marker, b' '];
+    let mut path = path.to_path_buf();
+    if !matches::category_properties(category).1 && !path.as_os_str().as_bytes().ends_with(b"/") {
+        path = walk::dir_match_path(&path);
+    }
+    ret.extend_from_slice(&renderer.render(&path, category));
+    return ret;
+}
+
+fn write_lines(cfg: &Config, lines: &[Vec<u8>]) {
+    if lines.len() == 0 {
+        return;
+    }
+    let mut stdout = std::io::stdout().lock();
+    let _ = stdout.write_all(&output::join_results(cfg, lines));
+    let _ = stdout.flush();
+}